@@ -8,6 +8,7 @@ const WAIT_FOR_FRAME = "WAIT";
 
 let debugMode = false; // when true debug panel is open
 let currentKeyPressed = -1;
+let isRewinding = false; // while backspace is held
 
 class Display {
   constructor(canvas, currentGame, canvasScale) {
@@ -31,11 +32,18 @@ class Display {
 }
 
 class Gameboy extends Display {
-  constructor(canvas, currentGame, canvasScale) {
+  constructor(canvas, currentGame, canvasScale, memory) {
     super(canvas, currentGame, canvasScale);
     super.changeCanvasDimensions(160, 144);
     this.emulator = Emulator.new();
-    this.colorPallete = ["#FFFFFF", "#AAAAAA", "#555555", "#000000"];
+    this.memory = memory; // wasm linear memory, frames are read straight out of it
+
+    // frames are drawn at 1x here and scaled up onto the visible canvas
+    this.frameCanvas = document.createElement("canvas");
+    this.frameCanvas.width = 160;
+    this.frameCanvas.height = 144;
+    this.frameCtx = this.frameCanvas.getContext("2d");
+    this.ctx.imageSmoothingEnabled = false;
 
     window.addEventListener("keydown", (e) => {
       switch (e.code) {
@@ -63,6 +71,9 @@ class Gameboy extends Display {
         case "Escape": // SELECT
           currentKeyPressed = 8;
           break;
+        case "Backspace": // hold to rewind
+          isRewinding = true;
+          break;
         default:
         // console.log(`Invalid key input: ${e.code}`);
       }
@@ -79,7 +90,14 @@ class Gameboy extends Display {
   }
 
   run(cartridge) {
-    this.emulator.load_catridge(new Uint8Array(cartridge));
+    try {
+      this.emulator.load_catridge(new Uint8Array(cartridge));
+    } catch (err) {
+      alert(`Couldn't load ROM: ${err}`);
+      return;
+    }
+    this.emulator.set_color_format(1); // RGBA8888
+    this.emulator.set_rewind(2, 16 * 1024); // snapshot every other frame, 16MB of history
 
     let debugPanelContainer = document.getElementById("debug-frame");
 
@@ -88,7 +106,9 @@ class Gameboy extends Display {
     frameTimer.onmessage = (e) => {
       if (e.data === RENDER_FRAME) {
         if (!super.isPaused) {
-          let display = this.emulator.render(currentKeyPressed);
+          if (!isRewinding || !this.emulator.rewind_step()) {
+            this.emulator.run_frame(currentKeyPressed);
+          }
           if (debugMode) {
             debugPanelContainer.innerHTML = "";
 
@@ -128,17 +148,14 @@ class Gameboy extends Display {
               debugPanelContainer.appendChild(scanlineContainer);
             }
           }
-          for (let row = 0; row < 144; row++) {
-            for (let col = 0; col < 160; col++) {
-              this.ctx.fillStyle = this.colorPallete[display[row * 160 + col]];
-              this.ctx.fillRect(
-                col * this.canvasScale,
-                row * this.canvasScale,
-                this.canvasScale,
-                this.canvasScale
-              );
-            }
-          }
+          // the view has to be recreated every frame, growing wasm memory detaches the old buffer
+          const frame = new Uint8ClampedArray(
+            this.memory.buffer,
+            this.emulator.framebuffer_ptr(),
+            this.emulator.framebuffer_len()
+          );
+          this.frameCtx.putImageData(new ImageData(frame, 160, 144), 0, 0);
+          this.ctx.drawImage(this.frameCanvas, 0, 0, 160 * this.canvasScale, 144 * this.canvasScale);
           frameTimer.postMessage(REQUEST_FRAME);
         }
       } else if (e.data === WAIT_FOR_FRAME) {
@@ -148,10 +165,10 @@ class Gameboy extends Display {
   }
 }
 
-init().then(() => {
+init().then((wasm) => {
   const canvas = document.getElementById("emulator");
 
-  const gameboy = new Gameboy(canvas, null, 4);
+  const gameboy = new Gameboy(canvas, null, 4, wasm.memory);
 
   const romUpload = document.getElementById("rom-upload");
   romUpload.addEventListener("change", function (e) {
@@ -170,7 +187,11 @@ init().then(() => {
 
     reader.onload = function () {
       var arrayBuffer = this.result;
-      gameboy.emulator.load_save_file(new Uint8Array(arrayBuffer));
+      try {
+        gameboy.emulator.load_save_file(new Uint8Array(arrayBuffer));
+      } catch (err) {
+        alert(`Couldn't load save file: ${err}`);
+      }
     };
     reader.readAsArrayBuffer(this.files[0]);
   });
@@ -202,6 +223,9 @@ init().then(() => {
   });
 });
 
-window.addEventListener("keyup", () => {
+window.addEventListener("keyup", (e) => {
+  if (e.code === "Backspace") {
+    isRewinding = false;
+  }
   currentKeyPressed = -1;
 });
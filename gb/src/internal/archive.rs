@@ -0,0 +1,288 @@
+use std::fmt;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use crate::internal::checksum::crc32;
+use crate::internal::patch::MAX_ROM_SIZE;
+use crate::internal::memory::Memory;
+
+const HEADER_END: usize = 0x150; // anything shorter can't have a cartridge header
+const CARTRIDGE_TYPE: usize = 0x147;
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    Truncated,
+    MissingDirectory,
+    Unsupported(&'static str), // zip features no ROM archive needs
+    UnsupportedCompression(u16), // zip method
+    Inflate(String),
+    ChecksumMismatch(String), // entry name
+    NoRom,
+    MultipleRoms(Vec<String>),
+    NotARom(usize), // length
+    UnsupportedCartridge(u8) // cartridge type in the header
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Truncated => write!(f, "archive ended early"),
+            ArchiveError::MissingDirectory => write!(f, "zip file has no central directory"),
+            ArchiveError::Unsupported(feature) => write!(f, "{} zip files aren't supported", feature),
+            ArchiveError::UnsupportedCompression(method) => write!(f, "unsupported zip compression method {}", method),
+            ArchiveError::Inflate(err) => write!(f, "couldn't decompress: {}", err),
+            ArchiveError::ChecksumMismatch(name) => write!(f, "{} is corrupt (CRC32 mismatch)", name),
+            ArchiveError::NoRom => write!(f, "archive has no .gb or .gbc file"),
+            ArchiveError::MultipleRoms(names) => write!(f, "archive has more than one ROM ({}), extract the one to play", names.join(", ")),
+            ArchiveError::NotARom(len) => write!(f, "{} bytes is too small to be a Game Boy ROM", len),
+            ArchiveError::UnsupportedCartridge(kind) => write!(f, "cartridge type 0x{:02X} isn't supported (or the file isn't a Game Boy ROM)", kind)
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ArchiveError> {
+    let bytes = bytes.get(offset..offset + 2).ok_or(ArchiveError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ArchiveError> {
+    let bytes = bytes.get(offset..offset + 4).ok_or(ArchiveError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    decompress_to_vec_with_limit(data, MAX_ROM_SIZE).map_err(|err| ArchiveError::Inflate(err.to_string()))
+}
+
+fn is_rom(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    !name.starts_with("__macosx/") && (name.ends_with(".gb") || name.ends_with(".gbc"))
+}
+
+struct Entry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    local_header: usize
+}
+
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT, entries are found through the central directory
+// since the local headers can leave their sizes to a data descriptor after the data
+fn extract_zip(zip: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let search_start = zip.len().saturating_sub(22 + 0xFFFF); // end of central directory record and the longest comment
+    let end_record = (search_start..zip.len().saturating_sub(21)).rev().find(|i| zip[*i..].starts_with(b"PK\x05\x06")).ok_or(ArchiveError::MissingDirectory)?;
+    let entries = read_u16(zip, end_record + 10)?;
+    let mut ptr = read_u32(zip, end_record + 16)? as usize;
+    if entries == 0xFFFF || ptr == 0xFFFFFFFF {
+        return Err(ArchiveError::Unsupported("ZIP64"))
+    }
+
+    let mut roms = vec![];
+    for _ in 0..entries {
+        if ptr > zip.len() || zip.get(ptr..ptr + 4) != Some(b"PK\x01\x02") { // offsets are checked before adding to them, usize is 32 bits on wasm
+            return Err(ArchiveError::MissingDirectory)
+        }
+        let name_len = read_u16(zip, ptr + 28)? as usize;
+        let name = zip.get(ptr + 46..ptr + 46 + name_len).ok_or(ArchiveError::Truncated)?;
+        let entry = Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: read_u16(zip, ptr + 8)?,
+            method: read_u16(zip, ptr + 10)?,
+            crc: read_u32(zip, ptr + 16)?,
+            compressed_size: read_u32(zip, ptr + 20)? as usize,
+            local_header: read_u32(zip, ptr + 42)? as usize
+        };
+        ptr += 46 + name_len + read_u16(zip, ptr + 30)? as usize + read_u16(zip, ptr + 32)? as usize;
+        if is_rom(&entry.name) {
+            roms.push(entry);
+        }
+    }
+
+    let entry = match roms.len() {
+        0 => return Err(ArchiveError::NoRom),
+        1 => roms.pop().unwrap(),
+        _ => return Err(ArchiveError::MultipleRoms(roms.into_iter().map(|entry| entry.name).collect()))
+    };
+    if entry.flags & 0x1 != 0 {
+        return Err(ArchiveError::Unsupported("encrypted"))
+    }
+
+    let header = entry.local_header;
+    if header > zip.len() || zip.get(header..header + 4) != Some(b"PK\x03\x04") {
+        return Err(ArchiveError::Truncated)
+    }
+    let start = header + 30 + read_u16(zip, header + 26)? as usize + read_u16(zip, header + 28)? as usize;
+    let data = start.checked_add(entry.compressed_size).and_then(|end| zip.get(start..end)).ok_or(ArchiveError::Truncated)?;
+    let rom = match entry.method {
+        0 => data.to_vec(), // stored
+        8 => inflate(data)?, // deflate
+        method => return Err(ArchiveError::UnsupportedCompression(method))
+    };
+    if crc32(&rom) != entry.crc {
+        return Err(ArchiveError::ChecksumMismatch(entry.name))
+    }
+    Ok(rom)
+}
+
+// https://www.rfc-editor.org/rfc/rfc1952, a single member with the CRC32 and size of the data at the end
+fn extract_gzip(gzip: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    if gzip.len() < 18 {
+        return Err(ArchiveError::Truncated)
+    }
+    let flags = gzip[3];
+    let mut ptr = 10;
+    if flags & 0x04 != 0 { // extra field
+        ptr += 2 + read_u16(gzip, ptr)? as usize;
+    }
+    for flag in [0x08, 0x10] { // file name, comment
+        if flags & flag != 0 {
+            ptr += 1 + gzip.get(ptr..).and_then(|rest| rest.iter().position(|byte| *byte == 0)).ok_or(ArchiveError::Truncated)?;
+        }
+    }
+    if flags & 0x02 != 0 { // header CRC
+        ptr += 2;
+    }
+
+    let trailer = gzip.len() - 8;
+    let rom = inflate(gzip.get(ptr..trailer).ok_or(ArchiveError::Truncated)?)?;
+    if crc32(&rom) != read_u32(gzip, trailer)? || rom.len() as u32 != read_u32(gzip, trailer + 4)? {
+        return Err(ArchiveError::ChecksumMismatch(String::from("gzip data")))
+    }
+    Ok(rom)
+}
+
+// ROM inside a zip or gzip file, other files are taken to be the ROM itself
+pub fn extract_rom(file: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+    let rom = if file.starts_with(b"PK\x03\x04") || file.starts_with(b"PK\x05\x06") {
+        extract_zip(&file)?
+    } else if file.starts_with(&[0x1F, 0x8B, 0x08]) { // deflate is the only gzip compression method
+        extract_gzip(&file)?
+    } else {
+        file
+    };
+    check_rom(&rom)?;
+    Ok(rom)
+}
+
+// the header has to be there and name an MBC the emulator has, loading anything else would panic
+pub fn check_rom(rom: &[u8]) -> Result<(), ArchiveError> {
+    if rom.len() < HEADER_END {
+        return Err(ArchiveError::NotARom(rom.len()))
+    }
+    if !Memory::is_supported_cartridge(rom[CARTRIDGE_TYPE]) {
+        return Err(ArchiveError::UnsupportedCartridge(rom[CARTRIDGE_TYPE]))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    fn rom(seed: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..0x8000).map(|i| ((i * 7) as u8).wrapping_add(seed) & 0x3F).collect();
+        rom[CARTRIDGE_TYPE] = 0x00;
+        rom
+    }
+
+    // zip with a local header and central directory entry for each (name, data), deflated
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = vec![];
+        let mut directory = vec![];
+        for (name, data) in files {
+            let compressed = compress_to_vec(data, 6);
+            let offset = zip.len() as u32;
+            let mut fields = vec![];
+            fields.extend_from_slice(&8u16.to_le_bytes()); // deflate
+            fields.extend_from_slice(&[0; 4]); // time, date
+            fields.extend_from_slice(&crc32(data).to_le_bytes());
+            fields.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0; 2]); // extra field length
+
+            zip.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00");
+            zip.extend_from_slice(&fields);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&compressed);
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00\x00\x00");
+            directory.extend_from_slice(&fields);
+            directory.extend_from_slice(&[0; 10]); // comment length, disk, attributes
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&directory_offset.to_le_bytes());
+        zip.extend_from_slice(&[0; 2]); // comment length
+        zip
+    }
+
+    #[test]
+    fn extracts_the_rom_from_a_zip() {
+        let rom = rom(1);
+        let file = zip(&[("readme.txt", b"have fun"), ("__MACOSX/._game.gb", b"resource fork"), ("Game.GB", &rom)]);
+        assert_eq!(extract_rom(file).unwrap(), rom);
+
+        assert_eq!(extract_rom(zip(&[("readme.txt", b"no rom here")])), Err(ArchiveError::NoRom));
+        let multiple = zip(&[("a.gb", &rom), ("b.gbc", &rom)]);
+        assert_eq!(extract_rom(multiple), Err(ArchiveError::MultipleRoms(vec![String::from("a.gb"), String::from("b.gbc")])));
+
+        let mut corrupt = zip(&[("game.gb", &rom)]);
+        corrupt[14] ^= 1; // local CRC, the central directory one is what gets checked
+        assert_eq!(extract_rom(corrupt.clone()).unwrap(), rom);
+        let directory = corrupt.len() - 22 - (46 + 7);
+        corrupt[directory + 16] ^= 1;
+        assert_eq!(extract_rom(corrupt), Err(ArchiveError::ChecksumMismatch(String::from("game.gb"))));
+    }
+
+    #[test]
+    fn extracts_the_rom_from_a_gzip() {
+        let rom = rom(2);
+        let mut gzip = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF]; // with a file name
+        gzip.extend_from_slice(b"game.gb\0");
+        gzip.extend(compress_to_vec(&rom, 6));
+        gzip.extend_from_slice(&crc32(&rom).to_le_bytes());
+        gzip.extend_from_slice(&(rom.len() as u32).to_le_bytes());
+        assert_eq!(extract_rom(gzip.clone()).unwrap(), rom);
+
+        let len = gzip.len();
+        gzip[len - 1] ^= 1;
+        assert_eq!(extract_rom(gzip), Err(ArchiveError::ChecksumMismatch(String::from("gzip data"))));
+    }
+
+    #[test]
+    fn plain_roms_pass_through_and_garbage_is_rejected() {
+        let rom = rom(3);
+        assert_eq!(extract_rom(rom.clone()).unwrap(), rom);
+        assert_eq!(extract_rom(vec![0; 0x20]), Err(ArchiveError::NotARom(0x20)));
+
+        let file = zip(&[("game.gb", &rom)]);
+        for len in 0..file.len() {
+            assert!(extract_rom(file[..len].to_vec()).is_err());
+        }
+    }
+
+    #[test]
+    fn unsupported_cartridge_types_are_rejected() {
+        let mut file = vec![0; 0x8000]; // a .7z or anything else that isn't a zip, gzip or ROM
+        file[..6].copy_from_slice(&[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C]);
+        file[CARTRIDGE_TYPE] = 0xFC; // Pocket Camera
+        assert_eq!(extract_rom(file.clone()), Err(ArchiveError::UnsupportedCartridge(0xFC)));
+
+        for kind in [0x00, 0x03, 0x0F, 0x13, 0x19, 0x1E] {
+            file[CARTRIDGE_TYPE] = kind;
+            assert!(extract_rom(file.clone()).is_ok(), "0x{:02X}", kind);
+        }
+        for kind in [0x04, 0x0E, 0x14, 0x18, 0x1F, 0xFF] {
+            file[CARTRIDGE_TYPE] = kind;
+            assert_eq!(check_rom(&file), Err(ArchiveError::UnsupportedCartridge(kind)));
+        }
+    }
+}
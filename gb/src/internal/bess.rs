@@ -0,0 +1,233 @@
+use std::fmt;
+
+// https://github.com/LIJI32/SameBoy/blob/master/BESS.md
+pub const CORE_BLOCK_LEN: usize = 0xD0;
+const MIN_CORE_BLOCK_LEN: usize = 0xC0; // older saves from this emulator left out the CGB palette buffers
+
+#[derive(Debug, PartialEq)]
+pub enum BessError {
+    MissingFooter,
+    FirstBlockOutOfBounds(u32),
+    BlockOutOfBounds([u8; 4]),
+    MissingEndBlock,
+    MissingCoreBlock,
+    CoreBlockTooShort(usize),
+    UnsupportedVersion(u16),
+    UnsupportedModel([u8; 4]),
+    BufferOutOfBounds(&'static str),
+    InvalidMbcBlock,
+    InvalidMbcRegister(u16),
+    UnsupportedStateVersion(u16),
+    StateTruncated,
+    StateTrailingData,
+    InvalidState(&'static str) // field that's out of range
+}
+
+impl fmt::Display for BessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BessError::MissingFooter => write!(f, "not a BESS file (missing footer)"),
+            BessError::FirstBlockOutOfBounds(offset) => write!(f, "first block offset 0x{:X} is outside the file", offset),
+            BessError::BlockOutOfBounds(name) => write!(f, "block {:?} runs past the end of the file", String::from_utf8_lossy(name)),
+            BessError::MissingEndBlock => write!(f, "missing END block"),
+            BessError::MissingCoreBlock => write!(f, "missing CORE block"),
+            BessError::CoreBlockTooShort(len) => write!(f, "CORE block is only {} bytes", len),
+            BessError::UnsupportedVersion(major) => write!(f, "unsupported BESS major version {}", major),
+            BessError::UnsupportedModel(model) => write!(f, "unsupported model {:?}, only DMG states can be loaded", String::from_utf8_lossy(model)),
+            BessError::BufferOutOfBounds(buffer) => write!(f, "{} buffer is outside the file", buffer),
+            BessError::InvalidMbcBlock => write!(f, "MBC block length must be divisible by 3"),
+            BessError::InvalidMbcRegister(addr) => write!(f, "invalid MBC register address 0x{:04X}", addr),
+            BessError::UnsupportedStateVersion(version) => write!(f, "save state was written by a newer version ({})", version),
+            BessError::StateTruncated => write!(f, "save state block ended early"),
+            BessError::StateTrailingData => write!(f, "save state block has trailing data"),
+            BessError::InvalidState(field) => write!(f, "save state block is corrupt (invalid {})", field)
+        }
+    }
+}
+
+pub struct Block<'a> {
+    pub name: [u8; 4],
+    pub data: &'a [u8]
+}
+
+pub struct Core<'a> {
+    pub pc: u16,
+    pub registers: [u8; 8], // F, A, C, B, E, D, L, H
+    pub sp: u16,
+    pub ime: bool,
+    pub ie: u8,
+    pub halted: bool,
+    pub io_registers: &'a [u8], // 0xFF00-0xFF7F
+
+    pub ram: &'a [u8],
+    pub vram: &'a [u8],
+    pub mbc_ram: &'a [u8],
+    pub oam: &'a [u8],
+    pub hram: &'a [u8]
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+// every block up to and including END, blocks this emulator doesn't know about are left for the caller to skip
+pub fn parse(file: &[u8]) -> Result<Vec<Block<'_>>, BessError> {
+    if file.len() < 8 || file[file.len() - 4..] != *b"BESS" {
+        return Err(BessError::MissingFooter)
+    }
+
+    let footer = file.len() - 8;
+    let first_block = read_u32(file, footer);
+    if first_block as usize > footer {
+        return Err(BessError::FirstBlockOutOfBounds(first_block))
+    }
+
+    let mut blocks = vec![];
+    let mut ptr = first_block as usize;
+    while ptr + 8 <= footer {
+        let name: [u8; 4] = file[ptr..ptr + 4].try_into().unwrap();
+        let len = read_u32(file, ptr + 4) as usize;
+        ptr += 8;
+        if len > footer - ptr {
+            return Err(BessError::BlockOutOfBounds(name))
+        }
+
+        blocks.push(Block { name, data: &file[ptr..ptr + len] });
+        ptr += len;
+        if name == *b"END " {
+            return Ok(blocks)
+        }
+    }
+    Err(BessError::MissingEndBlock)
+}
+
+// size and offset pair at `offset` in the CORE block pointing into the file
+fn buffer<'a>(file: &'a [u8], core: &[u8], offset: usize, name: &'static str) -> Result<&'a [u8], BessError> {
+    let size = read_u32(core, offset) as usize;
+    let start = read_u32(core, offset + 4) as usize;
+    match start.checked_add(size) {
+        Some(end) if end <= file.len() => Ok(&file[start..end]),
+        _ => Err(BessError::BufferOutOfBounds(name))
+    }
+}
+
+pub fn parse_core<'a>(file: &'a [u8], core: &'a [u8]) -> Result<Core<'a>, BessError> {
+    if core.len() < MIN_CORE_BLOCK_LEN {
+        return Err(BessError::CoreBlockTooShort(core.len()))
+    }
+
+    let major = read_u16(core, 0x00);
+    if major != 1 {
+        return Err(BessError::UnsupportedVersion(major))
+    }
+    let model: [u8; 4] = core[0x04..0x08].try_into().unwrap();
+    if model[0] != b'G' { // CGB and SGB states need hardware that isn't emulated
+        return Err(BessError::UnsupportedModel(model))
+    }
+
+    Ok(Core {
+        pc: read_u16(core, 0x08),
+        registers: core[0x0A..0x12].try_into().unwrap(),
+        sp: read_u16(core, 0x12),
+        ime: core[0x14] != 0,
+        ie: core[0x15],
+        halted: core[0x16] == 1, // 2 is stopped which is treated as running
+        io_registers: &core[0x18..0x98],
+        ram: buffer(file, core, 0x98, "RAM")?,
+        vram: buffer(file, core, 0xA0, "VRAM")?,
+        mbc_ram: buffer(file, core, 0xA8, "MBC RAM")?,
+        oam: buffer(file, core, 0xB0, "OAM")?,
+        hram: buffer(file, core, 0xB8, "HRAM")?
+    })
+}
+
+// (address, value) register writes
+pub fn parse_mbc(mbc: &[u8]) -> Result<Vec<(u16, u8)>, BessError> {
+    if !mbc.len().is_multiple_of(3) {
+        return Err(BessError::InvalidMbcBlock)
+    }
+
+    let mut writes = vec![];
+    for write in mbc.chunks(3) {
+        let addr = read_u16(write, 0);
+        if !matches!(addr, 0x0000..=0x7FFF | 0xA000..=0xBFFF) {
+            return Err(BessError::InvalidMbcRegister(addr))
+        }
+        writes.push((addr, write[2]));
+    }
+    Ok(writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut block = name.to_vec();
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn file(buffers: &[u8], blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = buffers.to_vec();
+        for block in blocks {
+            file.extend(block);
+        }
+        file.extend_from_slice(&(buffers.len() as u32).to_le_bytes());
+        file.extend_from_slice(b"BESS");
+        file
+    }
+
+    #[test]
+    fn unknown_blocks_are_returned_in_order() {
+        let file = file(&[0xAA; 4], &[block(b"NAME", b"SameBoy v0.16"), block(b"RTC ", &[0; 0x30]), block(b"SGB ", &[0; 0x39]), block(b"END ", &[])]);
+        let blocks = parse(&file).unwrap();
+        let names: Vec<_> = blocks.iter().map(|block| block.name).collect();
+        assert_eq!(names, [*b"NAME", *b"RTC ", *b"SGB ", *b"END "]);
+        assert_eq!(blocks[1].data.len(), 0x30);
+    }
+
+    #[test]
+    fn structural_errors() {
+        assert_eq!(parse(b"BESS").err(), Some(BessError::MissingFooter));
+        assert_eq!(parse(&file(&[], &[block(b"NAME", b"x")])).err(), Some(BessError::MissingEndBlock));
+
+        let mut truncated = file(&[], &[block(b"CORE", &[0; 0x10]), block(b"END ", &[])]);
+        truncated[4] = 0xFF; // CORE length
+        assert_eq!(parse(&truncated).err(), Some(BessError::BlockOutOfBounds(*b"CORE")));
+
+        let mut bad_offset = file(&[], &[block(b"END ", &[])]);
+        let footer = bad_offset.len() - 8;
+        bad_offset[footer] = 0x40;
+        assert_eq!(parse(&bad_offset).err(), Some(BessError::FirstBlockOutOfBounds(0x40)));
+    }
+
+    #[test]
+    fn core_buffers_must_be_inside_the_file() {
+        let mut core = vec![0; CORE_BLOCK_LEN];
+        core[0x00] = 1;
+        core[0x04..0x08].copy_from_slice(b"GD  ");
+        core[0x98..0x9C].copy_from_slice(&4u32.to_le_bytes()); // 4 bytes of RAM at offset 0
+        let buffers = [1, 2, 3, 4];
+        assert_eq!(parse_core(&buffers, &core).unwrap().ram, [1, 2, 3, 4]);
+
+        core[0x9C..0xA0].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse_core(&buffers, &core).err(), Some(BessError::BufferOutOfBounds("RAM")));
+
+        core[0x04] = b'C';
+        assert_eq!(parse_core(&buffers, &core).err(), Some(BessError::UnsupportedModel(*b"CD  ")));
+        assert_eq!(parse_core(&buffers, &core[..0x20]).err(), Some(BessError::CoreBlockTooShort(0x20)));
+    }
+
+    #[test]
+    fn mbc_registers_must_be_in_rom_or_ram_range() {
+        assert_eq!(parse_mbc(&[0x00, 0x20, 0x05, 0x00, 0xA0, 0x01]).unwrap(), [(0x2000, 0x05), (0xA000, 0x01)]);
+        assert_eq!(parse_mbc(&[0x00, 0xC0, 0x01]).err(), Some(BessError::InvalidMbcRegister(0xC000)));
+        assert_eq!(parse_mbc(&[0x00, 0x20]).err(), Some(BessError::InvalidMbcBlock));
+    }
+}
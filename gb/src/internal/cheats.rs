@@ -0,0 +1,216 @@
+use std::fmt;
+use serde_json::json;
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    InvalidCode(String),
+    InvalidAddress(u16) // outside of ROM for Game Genie, outside of RAM for GameShark
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidCode(code) => write!(f, "{:?} is not a Game Genie (ABC-DEF-GHI) or GameShark (01VVAAAA) code", code),
+            CheatError::InvalidAddress(addr) => write!(f, "code targets address 0x{:04X} which it can't patch", addr)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CheatKind {
+    GameGenie { addr: u16, value: u8, compare: Option<u8> }, // ROM reads at addr return value (only if they would have returned compare)
+    GameShark { bank: Option<u8>, addr: u16, value: u8 } // RAM write every frame, bank forces the SRAM bank
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cheat {
+    pub code: String, // normalized: "ABC-DEF-GHI", "ABC-DEF" or "01VVAAAA"
+    pub kind: CheatKind,
+    pub enabled: bool
+}
+
+fn digits(code: &str) -> Option<Vec<u8>> {
+    code.chars().filter(|c| !matches!(c, '-' | ' ')).map(|c| c.to_digit(16).map(|digit| digit as u8)).collect()
+}
+
+// https://gbdev.io/pandocs/Shark_Cheats.html
+pub fn parse(code: &str) -> Result<Cheat, CheatError> {
+    let invalid = || CheatError::InvalidCode(String::from(code));
+    let d = digits(code).ok_or_else(invalid)?;
+    let code: String = d.iter().map(|digit| format!("{:X}", digit)).collect();
+
+    let kind = match d.len() {
+        6 | 9 => { // Game Genie, ABC-DEF-GHI: AB new data, FCDE address xor F000, GI old data rotated left by 2 after xoring BA, H unused
+            let addr = (((d[5] as u16) << 12) | ((d[2] as u16) << 8) | ((d[3] as u16) << 4) | d[4] as u16) ^ 0xF000;
+            if addr > 0x7FFF {
+                return Err(CheatError::InvalidAddress(addr))
+            }
+            let compare = if d.len() == 9 { Some(((d[6] << 4) | d[8]).rotate_right(2) ^ 0xBA) } else { None };
+            CheatKind::GameGenie { addr, value: (d[0] << 4) | d[1], compare }
+        },
+        8 => { // GameShark, TTVVAAAA: TT type, VV value, AAAA address (low byte first)
+            let kind = (d[0] << 4) | d[1];
+            let addr = ((d[6] as u16) << 12) | ((d[7] as u16) << 8) | ((d[4] as u16) << 4) | d[5] as u16;
+            let bank = match kind {
+                0x01 => None, // whatever bank is mapped in
+                0x80..=0x8F => Some(kind & 0xF),
+                _ => return Err(invalid())
+            };
+            if !matches!(addr, 0xA000..=0xDFFF | 0xFF80..=0xFFFE) || (bank.is_some() && addr > 0xBFFF) {
+                return Err(CheatError::InvalidAddress(addr))
+            }
+            CheatKind::GameShark { bank, addr, value: (d[2] << 4) | d[3] }
+        },
+        _ => return Err(invalid())
+    };
+
+    let code = match d.len() {
+        6 => format!("{}-{}", &code[0..3], &code[3..6]),
+        9 => format!("{}-{}-{}", &code[0..3], &code[3..6], &code[6..9]),
+        _ => code
+    };
+    Ok(Cheat { code, kind, enabled: true })
+}
+
+// kept on the bus so Game Genie codes are seen by every ROM read (CPU and debugger alike)
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    rom_patches: bool // any enabled Game Genie codes, keeps ROM reads fast when there are none
+}
+
+impl Cheats {
+    // codes are matched in their normalized form so "abc def ghi" and "ABC-DEF-GHI" are the same cheat
+    fn find(&self, code: &str) -> Option<usize> {
+        let code = parse(code).ok()?.code;
+        self.cheats.iter().position(|cheat| cheat.code == code)
+    }
+
+    fn update_rom_patches(&mut self) {
+        self.rom_patches = self.cheats.iter().any(|cheat| cheat.enabled && matches!(cheat.kind, CheatKind::GameGenie { .. }));
+    }
+
+    // adding a code that's already there just enables it again
+    pub fn add(&mut self, code: &str) -> Result<(), CheatError> {
+        let cheat = parse(code)?;
+        match self.cheats.iter_mut().find(|existing| existing.code == cheat.code) {
+            Some(existing) => existing.enabled = true,
+            None => self.cheats.push(cheat)
+        }
+        self.update_rom_patches();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, code: &str) -> bool {
+        let Some(index) = self.find(code) else { return false };
+        self.cheats.remove(index);
+        self.update_rom_patches();
+        true
+    }
+
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        let Some(index) = self.find(code) else { return false };
+        self.cheats[index].enabled = enabled;
+        self.update_rom_patches();
+        true
+    }
+
+    pub fn patch_rom(&self, addr: u16, val: u8) -> u8 {
+        if !self.rom_patches {
+            return val
+        }
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            if let CheatKind::GameGenie { addr: patched, value, compare } = cheat.kind {
+                if patched == addr && compare.is_none_or(|compare| compare == val) {
+                    return value
+                }
+            }
+        }
+        val
+    }
+
+    // enabled GameShark codes as (bank, address, value)
+    pub fn ram_writes(&self) -> impl Iterator<Item = (Option<u8>, u16, u8)> + '_ {
+        self.cheats.iter().filter(|cheat| cheat.enabled).filter_map(|cheat| match cheat.kind {
+            CheatKind::GameShark { bank, addr, value } => Some((bank, addr, value)),
+            _ => None
+        })
+    }
+
+    // [{ code, kind: "gamegenie" | "gameshark", enabled }] in the order they were added
+    pub fn to_json(&self) -> String {
+        let cheats: Vec<_> = self.cheats.iter().map(|cheat| {
+            let kind = match cheat.kind {
+                CheatKind::GameGenie { .. } => "gamegenie",
+                CheatKind::GameShark { .. } => "gameshark"
+            };
+            json!({ "code": cheat.code, "kind": kind, "enabled": cheat.enabled })
+        }).collect();
+        json!(cheats).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::memory::Memory;
+
+    #[test]
+    fn codes_are_decoded() {
+        // 0x4A written over 0x3E at 0x1234
+        assert_eq!(parse("4a2-34e-1a2").unwrap(), Cheat {
+            code: String::from("4A2-34E-1A2"),
+            kind: CheatKind::GameGenie { addr: 0x1234, value: 0x4A, compare: Some(0x3E) },
+            enabled: true
+        });
+        assert_eq!(parse("00A 17B").unwrap().kind, CheatKind::GameGenie { addr: 0x4A17, value: 0x00, compare: None });
+        assert_eq!(parse("01FF34C1").unwrap().kind, CheatKind::GameShark { bank: None, addr: 0xC134, value: 0xFF });
+        assert_eq!(parse("8205_0A0").err(), Some(CheatError::InvalidCode(String::from("8205_0A0"))));
+        assert_eq!(parse("820500C0").err(), Some(CheatError::InvalidAddress(0xC000))); // banked writes only go to SRAM
+        assert_eq!(parse("0205FFC0").err(), Some(CheatError::InvalidCode(String::from("0205FFC0")))); // unknown type
+        assert_eq!(parse("010500E0").err(), Some(CheatError::InvalidAddress(0xE000)));
+        assert_eq!(parse("000-000").err(), Some(CheatError::InvalidAddress(0xF000)));
+    }
+
+    #[test]
+    fn game_genie_only_patches_matching_reads() {
+        let mut cheats = Cheats::default();
+        cheats.add("4A2-34E-1A2").unwrap();
+        assert_eq!(cheats.patch_rom(0x1234, 0x3E), 0x4A);
+        assert_eq!(cheats.patch_rom(0x1234, 0x3F), 0x3F);
+        assert_eq!(cheats.patch_rom(0x1235, 0x3E), 0x3E);
+
+        assert!(cheats.set_enabled("4a2 34e 1a2", false));
+        assert_eq!(cheats.patch_rom(0x1234, 0x3E), 0x3E);
+        cheats.add("4A2-34E-1A2").unwrap();
+        assert_eq!(cheats.to_json(), r#"[{"code":"4A2-34E-1A2","enabled":true,"kind":"gamegenie"}]"#);
+        assert!(cheats.remove("4A234E1A2"));
+        assert!(!cheats.remove("4A234E1A2"));
+        assert_eq!(cheats.to_json(), "[]");
+    }
+
+    #[test]
+    fn cheats_apply_through_the_bus() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + battery
+        rom[0x149] = 0x03; // 4 banks of SRAM
+        let mut memory = Memory::default();
+        memory.load_cartridge(rom);
+        memory.write(0x0000, 0x0A); // enable SRAM
+
+        memory.cheats.add("0112C0C0").unwrap();
+        memory.cheats.add("0134A0A0").unwrap();
+        memory.cheats.add("8256A0A0").unwrap();
+        memory.apply_cheats();
+        assert_eq!(memory.read(0xC0C0), 0x12);
+        assert_eq!(memory.sram[0x00A0], 0x34);
+        assert_eq!(memory.sram[0x40A0], 0x56);
+
+        memory.write(0xC0C0, 0x00);
+        memory.apply_cheats(); // every frame
+        assert_eq!(memory.read(0xC0C0), 0x12);
+
+        memory.cheats.add("4A2-34E").unwrap();
+        assert_eq!(memory.read(0x1234), 0x4A);
+    }
+}
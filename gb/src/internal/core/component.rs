@@ -1,8 +1,11 @@
 use crate::internal::ppu::Display;
-use crate ::internal::memory::Memory;
+use crate ::internal::memory::{Memory, BusState};
 use crate::internal::core::registers::{Register, Registers, Flag};
+use crate::internal::timeline::EventKind;
+use crate::internal::state::{self, check, StateReader, StateWriter, STATE_BLOCK};
+use crate::internal::bess::{self, BessError, CORE_BLOCK_LEN};
+use crate::internal::checksum;
 use crate::u32_to_little_endian;
-use std;
 
 pub struct CPU {
     pub registers: Registers,
@@ -14,7 +17,8 @@ pub struct CPU {
     tick_state: Option<TickState>,
     interrupt_tick_state: Option<InterruptTickState>,
     is_halted: bool,
-    halt_bug: bool,
+    halt_bug: bool, // next opcode fetch doesn't increment PC
+    pub frame_count: u64 // frames emulated since the cartridge was loaded
 }
 
 pub struct Instruction {
@@ -28,15 +32,72 @@ struct TickState {
     step: usize,
     b8: u8,
     b16: u8,
+
+    // enough to decode `instr` again when loading a save state, some micro-ops capture registers/PC when decoded
+    opcode: u8,
+    prefix_opcode: Option<u8>,
+    decoded_with: (Registers, u16, u16) // registers, PC and SP
+}
+
+const SAVED_REGISTERS: [Register; 8] = [Register::A, Register::B, Register::C, Register::D, Register::E, Register::H, Register::L, Register::F];
+const UNUSED_OPCODES: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD]; // decode_instr panics on these
+
+// a decoded save state, see CPU::read_state
+struct SavedState {
+    registers: Registers,
+    pc: u16,
+    sp: u16,
+    ime: bool,
+    should_enable_ime: usize,
+    is_halted: bool,
+    halt_bug: bool,
+    tick_state: Option<TickState>,
+    interrupt_tick_state: Option<InterruptTickState>,
+    bus: BusState,
+    frame_count: u64
 }
 
 struct InterruptTickState {
-    interrupt: Interrupt,
+    interrupt: Option<Interrupt>, // picked once the high byte of PC has been pushed
     step: usize
 }
 
+#[derive(Clone, Copy)]
 enum Interrupt {
-    VBLANK, STAT, TIMER
+    VBLANK, STAT, TIMER, SERIAL, JOYPAD
+}
+
+impl Interrupt {
+    fn from_bit(bit: u8) -> Option<Interrupt> {
+        match bit {
+            0 => Some(Interrupt::VBLANK),
+            1 => Some(Interrupt::STAT),
+            2 => Some(Interrupt::TIMER),
+            3 => Some(Interrupt::SERIAL),
+            4 => Some(Interrupt::JOYPAD),
+            _ => None
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        match self {
+            Interrupt::VBLANK => 0,
+            Interrupt::STAT => 1,
+            Interrupt::TIMER => 2,
+            Interrupt::SERIAL => 3,
+            Interrupt::JOYPAD => 4
+        }
+    }
+
+    fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBLANK => 0x0040,
+            Interrupt::STAT => 0x0048,
+            Interrupt::TIMER => 0x0050,
+            Interrupt::SERIAL => 0x0058,
+            Interrupt::JOYPAD => 0x0060
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -160,7 +221,11 @@ pub enum Byte {
 impl CPU {
     fn fetch_instr(&mut self) -> (u8, Vec<MicroInstr>) {
         let opcode = self.bus.read(self.pc);
-        self.pc += 1;
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
 
         (opcode, self.decode_instr(opcode))
     }
@@ -173,6 +238,15 @@ impl CPU {
     }
 
     fn execute(&mut self) {
+        if self.is_halted {
+            if self.bus.pending_interrupts() == 0 {
+                return
+            }
+            self.is_halted = false; // wakes up regardless of IME, this cycle is spent leaving HALT
+            self.bus.record_event(EventKind::HaltExited);
+            return
+        }
+
         if self.tick_state.is_none() {
             let instr = self.fetch_instr();
 
@@ -181,7 +255,10 @@ impl CPU {
                 step: 0,
                 is_prefix: instr.0 == 0xCB,
                 b8: 0,
-                b16: 0
+                b16: 0,
+                opcode: instr.0,
+                prefix_opcode: None,
+                decoded_with: (self.registers, self.pc, self.sp)
             };
 
             self.tick_state.get_or_insert(tick_state);
@@ -194,6 +271,7 @@ impl CPU {
 
             self.tick_state.as_mut().unwrap().instr = instr.1;
             self.tick_state.as_mut().unwrap().is_prefix = false;
+            self.tick_state.as_mut().unwrap().prefix_opcode = Some(instr.0);
             return
         }
 
@@ -392,7 +470,8 @@ impl CPU {
             },
             MicroInstr::SRLHL => {
                 self.registers.set_flag(Flag::C, self.bus.read(self.registers.get_hl()) & 0x1 == 1);
-                self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) >> 1);
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val >> 1);
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
                 self.registers.set_flag(Flag::H, false);
@@ -415,11 +494,14 @@ impl CPU {
             },
             MicroInstr::RRHL => {
                 let b0 = self.bus.read(self.registers.get_hl()) & 0x1;
-                self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) >> 1);
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val >> 1);
                 if self.registers.get_flag(Flag::C) == 1 {
-                    self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) | 0b10000000);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), val | 0b10000000);
                 } else {
-                    self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) & 0b01111111);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), val & 0b01111111);
                 }
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
@@ -455,7 +537,8 @@ impl CPU {
                 self.registers.set_flag(Flag::Z, self.bus.read(addr).wrapping_sub(1) == 0);
                 self.registers.set_flag(Flag::N, true);
                 self.registers.set_flag(Flag::H, ((self.bus.read(addr) & 0xF).wrapping_sub(1 & 0xF) & 0x10) == 0x10);
-                self.bus.write(addr, self.bus.read(addr).wrapping_sub(1));
+                let val = self.bus.read(addr);
+                self.bus.write(addr, val.wrapping_sub(1));
             },
             MicroInstr::ADDHLNN(val) => {
                 self.registers.set_flag(Flag::N, false);
@@ -520,7 +603,7 @@ impl CPU {
             MicroInstr::LDSPHL => self.sp = self.registers.get_hl(),
             MicroInstr::RETI => {
                 self.pc = ((state.b16 as u16) << 8) | (state.b8 as u16);
-                self.should_enable_ime = 2;
+                self.ime = true; // unlike EI there's no delay
             },
             MicroInstr::RST(addr) => self.pc = addr,
             MicroInstr::INCSP => self.sp = self.sp.wrapping_add(1),
@@ -589,7 +672,8 @@ impl CPU {
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()).wrapping_add(1) == 0);
                 self.registers.set_flag(Flag::N, false);
                 self.registers.set_flag(Flag::H, (((self.bus.read(self.registers.get_hl()) & 0xF).wrapping_add(1 & 0xF)) & 0x10) == 0x10);
-                self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()).wrapping_add(1));
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val.wrapping_add(1));
             },
             MicroInstr::RLCR(register) => {
                 let t = (self.registers[register] >> 7) & 0x1;
@@ -606,9 +690,11 @@ impl CPU {
             MicroInstr::RLCHL => {
                 let t = (self.bus.read(self.registers.get_hl()) >> 7) & 0x1;
                 if t == 1 {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) << 1) | 0b00000001);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val << 1) | 0b00000001);
                 } else {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) << 1) & 0b11111110);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val << 1) & 0b11111110);
                 }
                 self.registers.set_flag(Flag::C, t == 1);
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
@@ -642,9 +728,11 @@ impl CPU {
             MicroInstr::RRCHL => {
                 let t = self.bus.read(self.registers.get_hl()) & 0x1;
                 if t == 1 {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) >> 1) | 0b10000000);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val >> 1) | 0b10000000);
                 } else {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) >> 1) & 0b01111111);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val >> 1) & 0b01111111);
                 }
                 self.registers.set_flag(Flag::C, t == 1);
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
@@ -655,9 +743,11 @@ impl CPU {
                 let c = self.registers.get_flag(Flag::C);
                 self.registers.set_flag(Flag::C, (self.bus.read(self.registers.get_hl()) >> 7) & 0x1 == 0x1);
                 if c == 1 {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) << 1) | 0b00000001);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val << 1) | 0b00000001);
                 } else {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) << 1) & 0b11111110);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val << 1) & 0b11111110);
                 }
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
@@ -689,7 +779,8 @@ impl CPU {
             },
             MicroInstr::SLAHL => {
                 self.registers.set_flag(Flag::C, (self.bus.read(self.registers.get_hl()) >> 7) & 0x1 == 0x1);
-                self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) << 1);
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val << 1);
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
                 self.registers.set_flag(Flag::H, false);
@@ -705,9 +796,11 @@ impl CPU {
                 self.registers.set_flag(Flag::C, self.bus.read(self.registers.get_hl()) & 0x1 == 0x1);
                 let t = self.bus.read(self.registers.get_hl()) >> 7 & 0x1;
                 if t == 1 {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) >> 1) | 0b10000000);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val >> 1) | 0b10000000);
                 } else {
-                    self.bus.write(self.registers.get_hl(), (self.bus.read(self.registers.get_hl()) >> 1) & 0b01111111);
+                    let val = self.bus.read(self.registers.get_hl());
+                    self.bus.write(self.registers.get_hl(), (val >> 1) & 0b01111111);
                 }
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
@@ -733,7 +826,8 @@ impl CPU {
                 self.registers.set_flag(Flag::C, false);
             },
             MicroInstr::SWAPHL => {
-                self.bus.write(self.registers.get_hl(), ((self.bus.read(self.registers.get_hl()) & 0x0F) << 4) | ((self.bus.read(self.registers.get_hl()) & 0xF0) >> 4));
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), ((val & 0x0F) << 4) | ((val & 0xF0) >> 4));
                 self.registers.set_flag(Flag::Z, self.bus.read(self.registers.get_hl()) == 0);
                 self.registers.set_flag(Flag::N, false);
                 self.registers.set_flag(Flag::H, false);
@@ -763,20 +857,36 @@ impl CPU {
                 self.registers.set_flag(Flag::C, t == 1);
             },
             MicroInstr::RES(pos, register) => self.registers[register] &= !(1 << pos),
-            MicroInstr::RESHL(pos) => self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) & !(1 << pos)),
+            MicroInstr::RESHL(pos) => {
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val & !(1 << pos));
+            },
             MicroInstr::SET(pos, register) => self.registers[register] |= 1 << pos,
-            MicroInstr::SETHL(pos) => self.bus.write(self.registers.get_hl(), self.bus.read(self.registers.get_hl()) | 1 << pos),
+            MicroInstr::SETHL(pos) => {
+                let val = self.bus.read(self.registers.get_hl());
+                self.bus.write(self.registers.get_hl(), val | 1 << pos);
+            },
             MicroInstr::EI => self.should_enable_ime = 2,
-            MicroInstr::HALT => if !self.bus.flat_ram { self.is_halted = true },
-            MicroInstr::STOP => unimplemented!("encountered STOP instruction.")
+            MicroInstr::HALT => {
+                if self.bus.pending_interrupts() != 0 {
+                    // never halts, with EI right before the interrupt returns to the HALT itself
+                    // otherwise with IME off the byte after HALT gets read twice (halt bug)
+                    if self.should_enable_ime == 1 {
+                        self.pc = self.pc.wrapping_sub(1);
+                    } else if !self.ime {
+                        self.halt_bug = true;
+                    }
+                } else if !self.bus.flat_ram { // nothing can request an interrupt in flat_ram mode so it'd never wake up
+                    self.is_halted = true;
+                    self.bus.record_event(EventKind::HaltEntered);
+                }
+            },
+            MicroInstr::STOP => if !self.bus.flat_ram { // jsmoo only checks that it's a 1 byte NOP
+                unimplemented!("encountered STOP instruction.")
+            }
         }
 
-        if !self.is_halted {
-            state.step += 1;
-        } else if (self.bus.IE & self.bus.IF) != 0 {
-            self.is_halted = false;
-            state.step += 1;
-        }
+        state.step += 1;
 
         if state.step >= state.instr.len() {
             self.tick_state = None;
@@ -796,58 +906,58 @@ impl CPU {
             0 => state.step += 1,
             1 => state.step += 1,
             2 => {
-                self.sp -= 1;
+                self.sp = self.sp.wrapping_sub(1);
                 self.bus.write(self.sp, ((0xFF00 & self.pc) >> 8) as u8);
                 state.step += 1;
             },
             3 => {
-                self.sp -= 1;
+                // the push above can overwrite IE, if nothing is left to service the CPU ends up at 0x0000
+                let pending = self.bus.pending_interrupts();
+                if pending != 0 {
+                    let bit = pending.trailing_zeros() as u8; // lowest bit has the highest priority
+                    self.bus.IF &= !(1 << bit);
+                    self.bus.record_event(EventKind::InterruptServiced(bit));
+                    state.interrupt = Interrupt::from_bit(bit);
+                }
+
+                self.sp = self.sp.wrapping_sub(1);
                 self.bus.write(self.sp, (0x00FF & self.pc) as u8);
                 state.step += 1;
             },
             4 => {
-                match state.interrupt {
-                    Interrupt::VBLANK => self.pc = 0x0040,
-                    Interrupt::STAT => self.pc = 0x0048,
-                    Interrupt::TIMER => self.pc = 0x0050
-                }
+                self.pc = match state.interrupt {
+                    Some(interrupt) => interrupt.vector(),
+                    None => 0x0000
+                };
                 self.interrupt_tick_state = None;
             }
             _ => unreachable!()
         }
     }
 
-    pub fn next_frame(&mut self, keypress: i8) -> Display {
-        self.bus.keypress = keypress;
-        let mut cycles_to_timeout = 1000000; // TODO: Figure out that weird bug that crashes games from either interrupt or halt
-
-        while !self.bus.is_frame_rendered() && cycles_to_timeout > 0 { // represents 1 M-Cycle
-            if self.interrupt_tick_state.is_none() { self.execute() } else { self.execute_interrupt() } // either servicing interrupt or executing a normal instruction
-            self.bus.update_components();
-            self.bus.update_requested_interrupts();
-            if self.ime && self.tick_state.is_none() { // if interrupts are enabled service potential interrupts
-                if (self.bus.IE & self.bus.IF) != 0 { // an interrupt has been requested and can potentially be handled
-                    for i in 0..3 { // handles interrupts based on their priority
-                        if (self.bus.IF >> i) & 0x1 == 1 && (self.bus.IE >> i) & 0x1 == 1 { // interrupt has been requested and allowed by IE
-                            match i {
-                                0 => self.interrupt_tick_state.get_or_insert(InterruptTickState { interrupt: Interrupt::VBLANK, step: 0 }),
-                                1 => self.interrupt_tick_state.get_or_insert(InterruptTickState { interrupt: Interrupt::STAT, step: 0 }),
-                                2 => self.interrupt_tick_state.get_or_insert(InterruptTickState { interrupt: Interrupt::TIMER, step: 0 }),
-                                _ => unimplemented!("interrupt not implemented yet.")
-                            };
-                            self.bus.IF &= !(1 << i); // reset the bit that has been requested while processing
-                            self.ime = false; // disable interrupts to prevent anymore from being serviced while processing the current one
-                            break
-                        }
-                    }
-                }
+    fn step(&mut self) { // 1 M-cycle
+        if self.interrupt_tick_state.is_none() { self.execute() } else { self.execute_interrupt() } // either servicing interrupt or executing a normal instruction
+        self.bus.update_components();
+        self.bus.update_requested_interrupts();
+        if self.ime && self.tick_state.is_none() && self.bus.pending_interrupts() != 0 { // an interrupt has been requested and allowed by IE
+            if self.is_halted {
+                self.is_halted = false;
+                self.bus.record_event(EventKind::HaltExited);
             }
-            cycles_to_timeout -= 1;
+            self.interrupt_tick_state = Some(InterruptTickState { interrupt: None, step: 0 });
+            self.ime = false; // disable interrupts to prevent anymore from being serviced while processing the current one
         }
+    }
+
+    pub fn next_frame(&mut self, keypress: i8) -> Display {
+        self.bus.keypress = keypress;
+        self.bus.timeline.begin_frame();
+        self.bus.apply_cheats();
 
-        if cycles_to_timeout == 0 {
-            panic!("halt bug loop.")
+        while !self.bus.is_frame_rendered() {
+            self.step();
         }
+        self.frame_count += 1;
 
         return self.bus.get_display();
     }
@@ -860,16 +970,6 @@ impl CPU {
         bess_block
     }
 
-    fn next_block(&self, bess_encoding: &Vec<u8>, ptr: &mut usize) -> (String, u32) {
-        let name = std::str::from_utf8(&bess_encoding[*ptr..*ptr+4]).expect("invalid utf-8 sequence");
-        *ptr += 4;
-
-        let block_len = ((bess_encoding[*ptr + 3] as u32) << 24) | ((bess_encoding[*ptr + 2] as u32) << 16) | ((bess_encoding[*ptr + 1] as u32) << 8) | (bess_encoding[*ptr] as u32);
-        *ptr += (block_len as usize) + 4;
-
-        (String::from(name), block_len)
-    }
-
     fn create_core_block(&mut self, major_bess_ver: [u8; 2], minor_bess_ver: [u8; 2], model_identifier: &str) -> Vec<u8> {
         let mut core = vec![];
 
@@ -889,10 +989,194 @@ impl CPU {
 
         core.extend(mem_mapped_registers);
         core.append(&mut self.bus.bess_buffer_offsets); // appends then clears offsets created from copying large buffers at the beginning of the file ( Memory::aggregate_buffers() )
+        core.resize(CORE_BLOCK_LEN, 0x00); // no CGB palette buffers
 
         core
     }
 
+    fn save_registers(registers: &Registers, state: &mut StateWriter) {
+        for register in SAVED_REGISTERS {
+            state.write_u8(registers[register]);
+        }
+    }
+
+    fn load_registers(state: &mut StateReader) -> Result<Registers, BessError> {
+        let mut registers = Registers::default();
+        for register in SAVED_REGISTERS {
+            registers[register] = state.read_u8()?;
+        }
+        Ok(registers)
+    }
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        self.write_state(&mut state);
+        state.buffer
+    }
+
+    fn write_state(&mut self, state: &mut StateWriter) {
+        CPU::save_registers(&self.registers, state);
+        state.write_u16(self.pc);
+        state.write_u16(self.sp);
+        state.write_bool(self.ime);
+        state.write_usize(self.should_enable_ime);
+        state.write_bool(self.is_halted);
+        state.write_bool(self.halt_bug);
+
+        state.write_bool(self.tick_state.is_some());
+        if let Some(tick_state) = &self.tick_state {
+            state.write_bool(tick_state.is_prefix);
+            state.write_usize(tick_state.step);
+            state.write_bytes(&[tick_state.b8, tick_state.b16, tick_state.opcode]);
+            state.write_bool(tick_state.prefix_opcode.is_some());
+            state.write_u8(tick_state.prefix_opcode.unwrap_or(0));
+            CPU::save_registers(&tick_state.decoded_with.0, state);
+            state.write_u16(tick_state.decoded_with.1);
+            state.write_u16(tick_state.decoded_with.2);
+        }
+
+        state.write_bool(self.interrupt_tick_state.is_some());
+        if let Some(interrupt_tick_state) = &self.interrupt_tick_state {
+            state.write_usize(interrupt_tick_state.step);
+            state.write_u8(match interrupt_tick_state.interrupt {
+                Some(interrupt) => interrupt.bit(),
+                None => 0xFF
+            });
+        }
+
+        self.bus.save_state(state);
+        state.write_u64(self.frame_count);
+    }
+
+    fn load_state(&mut self, payload: &[u8]) -> Result<SavedState, BessError> {
+        let mut state = StateReader::new(payload);
+        let saved = self.read_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(BessError::StateTrailingData)
+        }
+        Ok(saved)
+    }
+
+    // only decodes, nothing changes until restore_state so a corrupted state can't leave the CPU half loaded
+    fn read_state(&mut self, state: &mut StateReader) -> Result<SavedState, BessError> {
+        let registers = CPU::load_registers(state)?;
+        let pc = state.read_u16()?;
+        let sp = state.read_u16()?;
+        let ime = state.read_bool()?;
+        let should_enable_ime = state.read_index(2, "EI delay")?;
+        let is_halted = state.read_bool()?;
+        let halt_bug = state.read_bool()?;
+
+        let mut tick_state = None;
+        if state.read_bool()? {
+            let is_prefix = state.read_bool()?;
+            let step = state.read_usize()?;
+            let [b8, b16, opcode] = state.read_bytes(3)?.try_into().unwrap();
+            let has_prefix_opcode = state.read_bool()?;
+            let prefix_opcode = state.read_u8()?;
+            let decoded_with = (CPU::load_registers(state)?, state.read_u16()?, state.read_u16()?);
+
+            check(!UNUSED_OPCODES.contains(&opcode), "opcode")?;
+            let instr = if has_prefix_opcode {
+                self.decode_prefix_instr(prefix_opcode)
+            } else {
+                // decode against the registers the instruction originally saw
+                let current = (self.registers, self.pc, self.sp);
+                (self.registers, self.pc, self.sp) = decoded_with;
+                let instr = self.decode_instr(opcode);
+                (self.registers, self.pc, self.sp) = current;
+                instr
+            };
+            check(if is_prefix { step == 0 } else { step < instr.len() }, "instruction step")?; // the CB opcode gets fetched first
+
+            tick_state = Some(TickState {
+                is_prefix,
+                instr,
+                step,
+                b8,
+                b16,
+                opcode,
+                prefix_opcode: if has_prefix_opcode { Some(prefix_opcode) } else { None },
+                decoded_with
+            });
+        }
+
+        let mut interrupt_tick_state = None;
+        if state.read_bool()? {
+            let step = state.read_index(4, "interrupt step")?;
+            let interrupt = match state.read_u8()? {
+                0xFF => None,
+                bit => Some(Interrupt::from_bit(bit).ok_or(BessError::InvalidState("interrupt"))?)
+            };
+            interrupt_tick_state = Some(InterruptTickState { interrupt, step });
+        }
+
+        Ok(SavedState {
+            registers,
+            pc,
+            sp,
+            ime,
+            should_enable_ime,
+            is_halted,
+            halt_bug,
+            tick_state,
+            interrupt_tick_state,
+            bus: self.bus.load_state(state)?,
+            frame_count: state.read_u64()?
+        })
+    }
+
+    fn restore_state(&mut self, saved: SavedState) {
+        self.registers = saved.registers;
+        self.pc = saved.pc;
+        self.sp = saved.sp;
+        self.ime = saved.ime;
+        self.should_enable_ime = saved.should_enable_ime;
+        self.is_halted = saved.is_halted;
+        self.halt_bug = saved.halt_bug;
+        self.tick_state = saved.tick_state;
+        self.interrupt_tick_state = saved.interrupt_tick_state;
+        self.bus.restore_state(saved.bus);
+        self.frame_count = saved.frame_count;
+    }
+
+    // everything needed to go back to this exact cycle, written into `snapshot` so its allocation gets reused
+    pub fn save_snapshot(&mut self, snapshot: &mut Vec<u8>) {
+        let mut state = StateWriter { buffer: std::mem::take(snapshot) };
+        state.buffer.clear();
+        self.write_state(&mut state);
+        self.bus.save_ram(&mut state);
+        *snapshot = state.buffer;
+    }
+
+    pub fn load_snapshot(&mut self, snapshot: &[u8]) -> Result<(), BessError> {
+        let mut state = StateReader::new(snapshot);
+        let saved = self.read_state(&mut state)?;
+        let ram = self.bus.load_ram(&mut state)?;
+        self.restore_state(saved);
+        self.bus.restore_ram(ram);
+        Ok(())
+    }
+
+    // digest of all emulated state (not frontend settings or debugging info), the snapshot stores every
+    // usize as u32 so identical inputs give identical hashes on native and wasm builds
+    pub fn state_hash(&mut self) -> u64 {
+        let mut snapshot = vec![];
+        self.save_snapshot(&mut snapshot);
+        checksum::fnv1a(&snapshot)
+    }
+
+    // back to how the loaded cartridge starts up, the frontend settings (colors, renderer, layers) are kept
+    pub fn power_on(&mut self) {
+        let mut fresh = CPU::default();
+        fresh.initialize_core();
+        fresh.bus.load_cartridge(self.bus.get_rom().to_vec());
+
+        let mut snapshot = vec![];
+        fresh.save_snapshot(&mut snapshot);
+        self.load_snapshot(&snapshot).expect("snapshot of the same cartridge");
+    }
+
     pub fn create_save_file(&mut self) -> Vec<u8> {
         let mut bess_encoding = vec![];
 
@@ -911,6 +1195,9 @@ impl CPU {
             bess_encoding.extend(self.create_block("MBC ", &mbc_block.unwrap()))
         }
 
+        let state_block = state::encode_block(self.save_state());
+        bess_encoding.extend(self.create_block(STATE_BLOCK, &state_block));
+
         bess_encoding.extend(self.create_block("END ", &[]));
 
         bess_encoding.extend_from_slice(&u32_to_little_endian(large_buffers.len() as u32));
@@ -919,106 +1206,56 @@ impl CPU {
         bess_encoding
     }
 
-    pub fn load_save_file(&mut self, file: Vec<u8>) {
-        if file[(file.len() - 4)..] != *("BESS".as_bytes()) {
-            panic!("Invalid save file.")
-        }
-
-        // acts as starting index for the first bess block
-        let mut file_ptr: usize = (((file[file.len() - 5] as u64) << 32) |  ((file[file.len() - 6] as u64) << 16) | ((file[file.len() - 7] as u64) << 8) | (file[file.len() - 8] as u64)) as usize;
-
-        loop {
-            let bess_block = self.next_block(&file,  &mut file_ptr);
-
-            match bess_block.0.as_str() {
-                "NAME" => (), // ignore for now
-                "INFO" => (), // ignore for now
-                "CORE" => {
-                    let chunk = &file[(file_ptr - (bess_block.1 as usize))..file_ptr];
-
-                    self.pc = ((chunk[0x09] as u16) << 8) | (chunk[0x08] as u16); 
-                    self.registers[Register::F] = chunk[0x0A];
-                    self.registers[Register::A] = chunk[0x0B];
-                    self.registers[Register::C] = chunk[0x0C];
-                    self.registers[Register::B] = chunk[0x0D];
-                    self.registers[Register::E] = chunk[0x0E];
-                    self.registers[Register::D] = chunk[0x0F];
-                    self.registers[Register::L] = chunk[0x10];
-                    self.registers[Register::H] = chunk[0x11];
-                    self.sp = ((chunk[0x13] as u16) << 8) | (chunk[0x12] as u16);
-                    self.ime = chunk[0x14] == 1;
-                    self.bus.IE = chunk[0x15]; 
-                    self.is_halted = chunk[0x16] == 1;
-
-                    for i in 0..0x80 {
-                        let addr = 0xFF00 + i;
-                        let val = chunk[(0x18 + i) as usize];
-
-                        match addr {
-                            0xFF04 => self.bus.timer.sysclock = (val as u16) << 8,
-                            0xFF46 => (),
-                            _ => self.bus.write(addr, val) // ignore don't care values ??
-                        }
-                    }
-
-                    let wram_size = ((chunk[0x9B] as u32) << 24) | ((chunk[0x9A] as u32) << 16) | ((chunk[0x99] as u32) << 8) | (chunk[0x98] as u32);
-                    let wram_offset = ((chunk[0x9F] as u32) << 24) | ((chunk[0x9E] as u32) << 16) | ((chunk[0x9D] as u32) << 8) | (chunk[0x9C] as u32);
-                    for i in 0..wram_size {
-                        self.bus.write((0xC000 + i) as u16, file[(wram_offset + i) as usize]);
-                    }
+    // every block (the private one included) is decoded before any state is touched, blocks that aren't used
+    // (NAME, INFO, RTC, SGB, ...) are skipped
+    pub fn load_save_file(&mut self, file: Vec<u8>) -> Result<(), BessError> {
+        let blocks = bess::parse(&file)?;
 
-                    let vram_size = ((chunk[0xA3] as u32) << 24) | ((chunk[0xA2] as u32) << 16) | ((chunk[0xA1] as u32) << 8) | (chunk[0xA0] as u32);
-                    let vram_offset = ((chunk[0xA7] as u32) << 24) | ((chunk[0xA6] as u32) << 16) | ((chunk[0xA5] as u32) << 8) | (chunk[0xA4] as u32);
-                    for i in 0..vram_size {
-                        self.bus.write((0x8000 + i) as u16, file[(vram_offset + i) as usize]);
-                    }
-
-                    let sram_size = ((chunk[0xAB] as u32) << 24) | ((chunk[0xAA] as u32) << 16) | ((chunk[0xA9] as u32) << 8) | (chunk[0xA8] as u32);
-                    let sram_offset = ((chunk[0xAF] as u32) << 24) | ((chunk[0xAE] as u32) << 16) | ((chunk[0xAD] as u32) << 8) | (chunk[0xAC] as u32);
-                    for i in 0..sram_size {
-                        self.bus.sram[i as usize] = file[(sram_offset + i) as usize];
-                    }
-
-                    let oam_size = ((chunk[0xB3] as u32) << 24) | ((chunk[0xB2] as u32) << 16) | ((chunk[0xB1] as u32) << 8) | (chunk[0xB0] as u32);
-                    let oam_offset = ((chunk[0xB7] as u32) << 24) | ((chunk[0xB6] as u32) << 16) | ((chunk[0xB5] as u32) << 8) | (chunk[0xB4] as u32);
-                    for i in 0..oam_size {
-                        self.bus.write((0xFE00 + i) as u16, file[(oam_offset + i) as usize]);
-                    }
+        let core_block = blocks.iter().find(|block| block.name == *b"CORE").ok_or(BessError::MissingCoreBlock)?;
+        let core = bess::parse_core(&file, core_block.data)?;
+        let mbc_writes = match blocks.iter().find(|block| block.name == *b"MBC ") {
+            Some(block) => bess::parse_mbc(block.data)?,
+            None => vec![]
+        };
+        let saved = match blocks.iter().find(|block| block.name == *STATE_BLOCK.as_bytes()) {
+            Some(block) => Some(self.load_state(&state::decode_block(block.data)?)?),
+            None => None
+        };
 
-                    let hram_size = ((chunk[0xBB] as u32) << 24) | ((chunk[0xBA] as u32) << 16) | ((chunk[0xB9] as u32) << 8) | (chunk[0xB8] as u32);
-                    let hram_offset = ((chunk[0xBF] as u32) << 24) | ((chunk[0xBE] as u32) << 16) | ((chunk[0xBD] as u32) << 8) | (chunk[0xBC] as u32);
-                    for i in 0..hram_size {
-                        self.bus.write((0xFF80 + i) as u16, file[(hram_offset + i) as usize]);
-                    }
-                },
-                "MBC " => {
-                    let chunk = &file[(file_ptr - (bess_block.1 as usize))..file_ptr];
+        self.pc = core.pc;
+        for (register, val) in [Register::F, Register::A, Register::C, Register::B, Register::E, Register::D, Register::L, Register::H].into_iter().zip(core.registers) {
+            self.registers[register] = val;
+        }
+        self.sp = core.sp;
+        self.ime = core.ime;
+        self.bus.IE = core.ie;
+        self.is_halted = core.halted;
+
+        for (i, val) in core.io_registers.iter().enumerate() {
+            match 0xFF00 + i as u16 {
+                0xFF04 => self.bus.timer.sysclock = (*val as u16) << 8,
+                0xFF46 => (),
+                addr => self.bus.write(addr, *val) // ignore don't care values ??
+            }
+        }
 
-                    if chunk.len() % 3 != 0 {
-                        panic!("MBC bess block must be divisible by 3.")
-                    }
+        for (base, buffer) in [(0xC000, core.ram), (0x8000, core.vram), (0xA000, core.mbc_ram), (0xFE00, core.oam), (0xFF80, core.hram)] {
+            self.bus.load_buffer(base, buffer);
+        }
 
-                    for i in 0..(chunk.len() / 3) {
-                        let offset = i * 3;
-                        let addr = ((chunk[offset + 1] as u16) << 8) | (chunk[offset + 0x00] as u16);
+        for (addr, val) in mbc_writes {
+            self.bus.write(addr, val);
+        }
 
-                        if addr <= 0x7FFF || (addr >= 0xA000 || addr <= 0xBFFF) {
-                            self.bus.write(addr, chunk[offset + 2]);
-                        } else {
-                            panic!("recieved invalid address for MBC register.");
-                        }
-                    }
-                },
-                "XOAM" => (), 
-                "END " => break,
-                _ => unimplemented!("Block not handled yet: ({})", bess_block.0)
+        match saved {
+            Some(saved) => self.restore_state(saved), // overrides whatever the BESS blocks set
+            None => { // plain BESS files don't have the mid-instruction state, reset CPU state and any requested interrupts
+                self.tick_state = None;
+                self.interrupt_tick_state = None;
+                self.bus.IF = 0x00;
             }
         }
-
-        // reset CPU state and any requested interrupts
-        self.tick_state = None;
-        self.interrupt_tick_state = None;
-        self.bus.IF = 0x00;
+        Ok(())
     }
 
     // manually sets registers to skip the boot rom
@@ -1051,7 +1288,8 @@ impl Default for CPU {
             should_enable_ime: 0,
             interrupt_tick_state: None,
             is_halted: false,
-            halt_bug: false
+            halt_bug: false,
+            frame_count: 0
         }
     }
 }
@@ -1145,7 +1383,10 @@ mod tests {
                     step: 0,
                     is_prefix: false,
                     b8: 0,
-                    b16: 0
+                    b16: 0,
+                    opcode: if prefixed { 0xCB } else { opcode_num },
+                    prefix_opcode: if prefixed { Some(opcode_num) } else { None },
+                    decoded_with: (cpu.registers, cpu.pc, cpu.sp)
                 });
 
                 let mut steps = if prefixed { 1 } else { 0 };
@@ -1177,6 +1418,517 @@ mod tests {
         }
     }
 
+    // program runs from WRAM with an empty ROM mapped in
+    fn setup_cpu(program: &[u8]) -> CPU {
+        let mut cpu = CPU::default();
+        cpu.bus.load_cartridge(vec![0x00; 0x8000]);
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0xC000 + i as u16, *byte);
+        }
+        cpu.pc = 0xC000;
+        cpu.sp = 0xDFF0;
+        cpu
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        let mut cpu = setup_cpu(&[0x76, 0x3C, 0x00]); // HALT, INC A, NOP
+        cpu.bus.IE = 0x04;
+        cpu.bus.IF = 0x04;
+
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert!(!cpu.is_halted);
+        assert_eq!(cpu.registers[Register::A], 2);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+
+    #[test]
+    fn halt_wakes_up_without_ime() {
+        let mut cpu = setup_cpu(&[0x76, 0x3C, 0x00]);
+        cpu.bus.IE = 0x04;
+
+        for _ in 0..100 {
+            cpu.step();
+        }
+        assert!(cpu.is_halted);
+        assert_eq!(cpu.pc, 0xC001);
+
+        cpu.bus.IF |= 0x04;
+        cpu.step(); // leaving HALT
+        cpu.step();
+        assert!(!cpu.is_halted);
+        assert_eq!(cpu.registers[Register::A], 1);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.bus.IF & 0x04, 0x04); // not serviced
+    }
+
+    #[test]
+    fn ei_before_halt_returns_to_halt() {
+        let mut cpu = setup_cpu(&[0xFB, 0x76]); // EI, HALT
+        cpu.bus.IE = 0x01;
+        cpu.bus.IF = 0x01;
+
+        for _ in 0..7 {
+            cpu.step();
+        }
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.bus.read(cpu.sp), 0x01); // return address is the HALT
+        assert_eq!(cpu.bus.read(cpu.sp + 1), 0xC0);
+    }
+
+    #[test]
+    fn halted_frame_with_lcd_off_completes() {
+        let mut cpu = setup_cpu(&[0x76]);
+        cpu.next_frame(-1);
+        assert!(cpu.is_halted);
+    }
+
+    fn run_until_dispatched(cpu: &mut CPU) {
+        cpu.step();
+        while cpu.interrupt_tick_state.is_some() {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn interrupts_serviced_by_priority() {
+        for (pending, vector) in [(0x1F, 0x0040), (0x1E, 0x0048), (0x1C, 0x0050), (0x18, 0x0058), (0x10, 0x0060)] {
+            let mut cpu = setup_cpu(&[0x00]);
+            cpu.ime = true;
+            cpu.bus.IE = 0x1F;
+            cpu.bus.IF = pending;
+
+            run_until_dispatched(&mut cpu);
+            assert_eq!(cpu.pc, vector);
+            assert_eq!(cpu.bus.IF & 0x1F, pending & (pending - 1)); // only the serviced bit is cleared
+            assert!(!cpu.ime);
+        }
+    }
+
+    #[test]
+    fn ie_push_cancels_dispatch() {
+        let mut cpu = setup_cpu(&[0x00]);
+        cpu.ime = true;
+        cpu.sp = 0x0000; // high byte of PC (0xC0) lands in IE
+        cpu.bus.IE = 0x01;
+        cpu.bus.IF = 0x01;
+
+        run_until_dispatched(&mut cpu);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.bus.IE, 0xC0);
+        assert_eq!(cpu.bus.IF & 0x1F, 0x01); // still pending
+    }
+
+    #[test]
+    fn ie_push_redirects_dispatch() {
+        let mut cpu = setup_cpu(&[]);
+        cpu.pc = 0xC400; // pushing 0xC4 leaves only TIMER enabled
+        cpu.ime = true;
+        cpu.sp = 0x0000;
+        cpu.bus.IE = 0x05;
+        cpu.bus.IF = 0x05;
+
+        run_until_dispatched(&mut cpu);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.bus.IF & 0x1F, 0x01);
+    }
+
+    #[test]
+    fn ie_push_of_low_byte_is_too_late() {
+        let mut cpu = setup_cpu(&[0x00]);
+        cpu.ime = true;
+        cpu.sp = 0x0001; // low byte of PC (0x01) lands in IE
+        cpu.bus.IE = 0x04;
+        cpu.bus.IF = 0x04;
+
+        run_until_dispatched(&mut cpu);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.bus.IE, 0x01);
+    }
+
+    #[test]
+    fn ei_enables_after_next_instruction() {
+        let mut cpu = setup_cpu(&[0xFB, 0x00, 0x00]); // EI, NOP, NOP
+        cpu.bus.IE = 0x01;
+        cpu.bus.IF = 0x01;
+
+        cpu.step();
+        assert!(!cpu.ime);
+        assert!(cpu.interrupt_tick_state.is_none());
+
+        run_until_dispatched(&mut cpu);
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.bus.read(cpu.sp), 0x02); // serviced right after the NOP
+    }
+
+    #[test]
+    fn ei_followed_by_di_never_enables() {
+        let mut cpu = setup_cpu(&[0xFB, 0xF3, 0x00, 0x00]); // EI, DI, NOP, NOP
+        cpu.bus.IE = 0x01;
+        cpu.bus.IF = 0x01;
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0xC004);
+    }
+
+    #[test]
+    fn reti_enables_immediately() {
+        let mut cpu = setup_cpu(&[0xD9]); // RETI
+        cpu.sp = 0xDFEE;
+        cpu.bus.write(0xDFEE, 0x00); // returns to 0xC100
+        cpu.bus.write(0xDFEF, 0xC1);
+        cpu.bus.IE = 0x01;
+        cpu.bus.IF = 0x01;
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert!(cpu.interrupt_tick_state.is_some());
+
+        run_until_dispatched(&mut cpu);
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.bus.read(cpu.sp), 0x00);
+        assert_eq!(cpu.bus.read(cpu.sp + 1), 0xC1);
+    }
+
+    #[test]
+    fn lazy_components_match_stepping_every_cycle() {
+        let mut cores = [CPU::default(), CPU::default()];
+        for core in cores.iter_mut() {
+            core.initialize_core();
+            core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        }
+        cores[1].bus.set_timeline_enabled(true); // catches up every cycle to stamp events
+
+        for _ in 0..45 { // the test result is on screen by then
+            let lazy = cores[0].next_frame(-1);
+            let stepped = cores[1].next_frame(-1);
+            assert_eq!(lazy, stepped);
+            assert_eq!(cores[0].pc, cores[1].pc);
+            assert_eq!(cores[0].bus.timer.sysclock, cores[1].bus.timer.sysclock);
+        }
+
+        // turning the LCD on has to bring VBLANK forward from where the LCD off timeline had it
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x40..0x42].copy_from_slice(&[0x18, 0xFE]); // VBLANK handler: JR -2
+        let program = [
+            0xAF, 0xE0, 0x40, // XOR A, LDH (LCDC),A - LCD off
+            0xE0, 0x0F, 0x3C, 0xE0, 0xFF, // LDH (IF),A, INC A, LDH (IE),A - only VBLANK
+            0x06, 0x00, 0xFB, // LD B,0x00, EI
+            0x3E, 0x91, 0xE0, 0x40, // LD A,0x91, LDH (LCDC),A - LCD on
+            0x04, 0x18, 0xFD // INC B, JR -3
+        ];
+        let mut cores = [CPU::default(), CPU::default()];
+        for core in cores.iter_mut() {
+            core.bus.load_cartridge(rom.clone());
+            for (i, byte) in program.iter().enumerate() {
+                core.bus.write(0xC000 + i as u16, *byte);
+            }
+            core.pc = 0xC000;
+            core.sp = 0xDFF0;
+        }
+        cores[1].bus.set_timeline_enabled(true);
+
+        for _ in 0..20000 {
+            cores[0].step();
+            cores[1].step();
+        }
+        assert!(matches!(cores[1].pc, 0x0040..=0x0042)); // stuck in the handler
+        assert_eq!(cores[0].pc, cores[1].pc);
+        assert_eq!(cores[0].registers[Register::B], cores[1].registers[Register::B]);
+    }
+
+    #[test]
+    fn scanline_renderer_matches_fifo_renderer() {
+        let mut cores = [CPU::default(), CPU::default()];
+        for core in cores.iter_mut() {
+            core.initialize_core();
+            core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        }
+        cores[1].bus.set_renderer(1);
+
+        for _ in 0..45 {
+            assert_eq!(cores[1].next_frame(-1), cores[0].next_frame(-1));
+        }
+    }
+
+    #[test]
+    fn framebuffer_is_reused_between_frames() {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+
+        let display = core.next_frame(-1);
+        core.bus.update_framebuffer();
+        assert_eq!(core.bus.get_framebuffer(), display); // raw shades come straight from the LCD
+
+        core.bus.set_color_format(1);
+        assert_eq!(core.bus.get_framebuffer().len(), 23040 * 4);
+        let ptr = core.bus.get_framebuffer().as_ptr();
+        for _ in 0..3 {
+            core.next_frame(-1);
+            core.bus.update_framebuffer();
+            assert_eq!(core.bus.get_framebuffer().as_ptr(), ptr);
+        }
+    }
+
+    #[test]
+    fn save_state_resumes_on_the_same_cycle() {
+        let rom = fs::read("./tests/blargg/roms/2.gb").unwrap();
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(rom.clone());
+
+        for _ in 0..10 {
+            core.next_frame(-1);
+        }
+        while core.tick_state.as_ref().map_or(true, |state| state.step == 0) { // stop in the middle of an instruction
+            core.step();
+        }
+        let save_file = core.create_save_file();
+
+        let mut resumed = CPU::default();
+        resumed.bus.load_cartridge(rom);
+        resumed.load_save_file(save_file).unwrap();
+        assert_eq!(resumed.tick_state.as_ref().map(|state| state.step), core.tick_state.as_ref().map(|state| state.step));
+
+        for _ in 0..20 {
+            assert_eq!(resumed.next_frame(-1), core.next_frame(-1));
+            assert_eq!(resumed.pc, core.pc);
+            assert_eq!(resumed.bus.timer.sysclock, core.bus.timer.sysclock);
+        }
+    }
+
+    #[test]
+    fn identical_runs_have_identical_state_hashes() {
+        let rom = fs::read("./tests/blargg/roms/2.gb").unwrap();
+        let boot = || {
+            let mut core = CPU::default();
+            core.initialize_core();
+            core.bus.load_cartridge(rom.clone());
+            core
+        };
+        let inputs = |frame: usize| [-1, 1, 5, -1, 7, 2][frame % 6];
+
+        let mut hashes = vec![];
+        let mut core = boot();
+        for frame in 0..60 {
+            core.next_frame(inputs(frame));
+            hashes.push(core.state_hash());
+        }
+        // has to be the same on every platform, only update it when the state layout or emulation changes on purpose
+        assert_eq!(hashes[59], 0x1AE18C15FCF5EDF7);
+
+        let mut again = boot();
+        for (frame, hash) in hashes.iter().enumerate() {
+            again.next_frame(inputs(frame));
+            assert_eq!(again.state_hash(), *hash, "frame {}", frame + 1);
+        }
+
+        let mut unhashed = boot(); // hashing catches the components up, which mustn't change anything
+        for frame in 0..60 {
+            unhashed.next_frame(inputs(frame));
+        }
+        assert_eq!(unhashed.state_hash(), hashes[59]);
+
+        let mut first_half = boot();
+        for frame in 0..30 {
+            first_half.next_frame(inputs(frame));
+        }
+        let mut resumed = boot();
+        resumed.load_save_file(first_half.create_save_file()).unwrap();
+        assert_eq!(resumed.state_hash(), hashes[29]);
+        for (frame, hash) in hashes.iter().enumerate().skip(30) {
+            resumed.next_frame(inputs(frame));
+            assert_eq!(resumed.state_hash(), *hash, "frame {}", frame + 1);
+        }
+    }
+
+    fn setup_save_file() -> (CPU, Vec<u8>) {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        for _ in 0..10 {
+            core.next_frame(-1);
+        }
+        let save_file = core.create_save_file();
+        (core, save_file)
+    }
+
+    // rebuilds a save file with `blocks` in place of the ones after the large buffers
+    fn replace_blocks(save_file: &[u8], blocks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let first_block = u32::from_le_bytes(save_file[save_file.len() - 8..save_file.len() - 4].try_into().unwrap());
+        let mut file = save_file[..first_block as usize].to_vec();
+        for (name, data) in blocks {
+            file.extend_from_slice(*name);
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(data);
+        }
+        file.extend_from_slice(&first_block.to_le_bytes());
+        file.extend_from_slice(b"BESS");
+        file
+    }
+
+    #[test]
+    fn bess_file_with_foreign_blocks_loads() {
+        let (mut core, save_file) = setup_save_file();
+        let blocks = bess::parse(&save_file).unwrap();
+        let data = |name: &[u8; 4]| blocks.iter().find(|block| block.name == *name).unwrap().data.to_vec();
+
+        // what SameBoy writes for a DMG game with a real time clock, and no private block
+        let file = replace_blocks(&save_file, &[
+            (b"NAME", b"SameBoy v0.16.3".to_vec()),
+            (b"INFO", data(b"INFO")),
+            (b"CORE", data(b"CORE")),
+            (b"XOAM", vec![0; 0x60]),
+            (b"RTC ", vec![0; 0x30]),
+            (b"HUC3", vec![0; 0x11]),
+            (b"SGB ", vec![0; 0x39]),
+            (b"END ", vec![])
+        ]);
+
+        let pc = core.pc;
+        core.next_frame(-1);
+        core.load_save_file(file).unwrap();
+        assert_eq!(core.pc, pc);
+        assert!(core.tick_state.is_none());
+    }
+
+    #[test]
+    fn corrupted_state_blocks_are_rejected() {
+        let (mut core, _) = setup_save_file();
+        let payload = core.save_state();
+        let mut bus = StateWriter::default();
+        core.bus.save_state(&mut bus);
+        // VRAM, OAM and the LCD come after the bus registers (8 bytes) and the timer (8 bytes)
+        let ppu_buffers = payload.len() - 8 - bus.buffer.len() + 16;
+        let buffers = ppu_buffers..ppu_buffers + 0x2000 + 0xA0 + 2 * 23040;
+
+        let mut corrupted = payload.clone();
+        corrupted[buffers.start + 0x20A0] = 4;
+        assert_eq!(core.load_state(&corrupted).err(), Some(BessError::InvalidState("LCD shade")));
+        assert_eq!(core.load_state(&payload[..payload.len() - 1]).err(), Some(BessError::StateTruncated));
+        assert_eq!(core.load_state(&[payload.as_slice(), &[0]].concat()).err(), Some(BessError::StateTrailingData));
+
+        // whatever gets through has to keep the PPU and timer running (a corrupted PC can send the CPU into an
+        // unused opcode just like a crashing game would)
+        for i in (0..payload.len()).filter(|i| !buffers.contains(i)) {
+            assert_eq!(core.load_state(&payload[..i]).err(), Some(BessError::StateTruncated));
+            for val in [0x01, 0x05, 0xFF] {
+                let mut corrupted = payload.clone();
+                corrupted[i] = val;
+                if let Ok(saved) = core.load_state(&corrupted) {
+                    core.restore_state(saved);
+                    for _ in 0..1000 {
+                        core.bus.update_components();
+                        core.bus.update_requested_interrupts();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_bess_file_leaves_state_untouched() {
+        let (mut core, save_file) = setup_save_file();
+        let blocks = bess::parse(&save_file).unwrap();
+        let mut core_block = blocks.iter().find(|block| block.name == *b"CORE").unwrap().data.to_vec();
+        core_block[0x9C..0xA0].copy_from_slice(&u32::MAX.to_le_bytes()); // RAM offset
+
+        let file = replace_blocks(&save_file, &[(b"CORE", core_block), (b"END ", vec![])]);
+        let pc = core.pc;
+        assert_eq!(core.load_save_file(file), Err(BessError::BufferOutOfBounds("RAM")));
+        assert_eq!(core.pc, pc);
+
+        let file = replace_blocks(&save_file, &[(b"NAME", vec![]), (b"END ", vec![])]);
+        assert_eq!(core.load_save_file(file), Err(BessError::MissingCoreBlock));
+    }
+
+    // one SRAM bank, so any other bank selected in the MBC block is out of range
+    fn setup_mbc5_save_file() -> (CPU, Vec<u8>) {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x147] = 0x1B; // MBC5 + RAM + battery
+        rom[0x149] = 0x02;
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(rom);
+        for _ in 0..10 {
+            core.next_frame(-1);
+        }
+        let save_file = core.create_save_file();
+        (core, save_file)
+    }
+
+    fn fuzz_save_file(mut core: CPU, save_file: Vec<u8>) {
+        let blocks = bess::parse(&save_file).unwrap();
+        let block_range = |name: &[u8]| {
+            let block = blocks.iter().find(|block| block.name == name).unwrap();
+            let start = block.data.as_ptr() as usize - save_file.as_ptr() as usize;
+            start..start + block.data.len()
+        };
+        let core_block = block_range(b"CORE");
+        let state_block = block_range(STATE_BLOCK.as_bytes());
+        core.load_save_file(save_file.clone()).unwrap();
+        let hash = core.state_hash();
+
+        let mut seed: u32 = 0x12345678;
+        let mut random = move || { // xorshift
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+
+        for i in 0..2400 {
+            let mut file = save_file.clone();
+            match i % 6 {
+                0 => { // flip bytes around the last blocks and footer where lengths and offsets live
+                    for _ in 0..(1 + random() % 8) {
+                        let offset = file.len() - 1 - (random() % 0x200);
+                        file[offset] = random() as u8;
+                    }
+                },
+                1 => file.truncate(random() % file.len()),
+                2 => file[core_block.start + random() % core_block.len()] = 0xFF, // a length or offset in the CORE block
+                3 => { // private block, applied last so it must not fail after CORE has been
+                    for _ in 0..(1 + random() % 4) {
+                        file[state_block.start + random() % state_block.len()] = random() as u8;
+                    }
+                },
+                4 => { // MBC block with RAM enabled, any bank selected and writes into the SRAM window
+                    let mut writes = vec![0x00, 0x00, 0x0A, 0x00, 0x40, random() as u8];
+                    for _ in 0..(random() % 8) {
+                        let addr = if random() % 2 == 0 { random() % 0x8000 } else { 0xA000 + random() % 0x2000 } as u16;
+                        writes.extend_from_slice(&addr.to_le_bytes());
+                        writes.push(random() as u8);
+                    }
+                    let replaced: Vec<_> = blocks.iter().map(|block| (&block.name, if block.name == *b"MBC " { writes.clone() } else { block.data.to_vec() })).collect();
+                    file = replace_blocks(&save_file, &replaced);
+                },
+                _ => file = (0..(random() % 64)).map(|_| random() as u8).chain(*b"BESS").collect()
+            }
+
+            if core.load_save_file(file).is_ok() {
+                core.load_save_file(save_file.clone()).unwrap();
+            }
+            assert_eq!(core.state_hash(), hash, "iteration {}", i);
+        }
+    }
+
+    #[test]
+    fn fuzzed_bess_files_never_panic() {
+        let (core, save_file) = setup_save_file();
+        fuzz_save_file(core, save_file);
+
+        let (core, save_file) = setup_mbc5_save_file();
+        fuzz_save_file(core, save_file);
+    }
+
     // #[test] WILL I EVER PASS THIS T_T
     // fn blargg_cpu_instr_tests() {
     //     let files = fs::read_dir("./tests/blargg/roms").unwrap();
@@ -6,15 +6,15 @@ use crate::internal::core::registers::{Register, Flag};
 impl CPU {
     pub fn decode_instr(&self, opcode: u8) -> Vec<MicroInstr> {
         let instruction = match opcode {
-            0x26 => Instruction{ name: format!("LD H, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::H)] },
-            0x0E => Instruction{ name: format!("LD C, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::C)] },
-            0x06 => Instruction{ name: format!("LD B, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::B)] },
-            0x2E => Instruction{ name: format!("LD L, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::L)] },
-            0x16 => Instruction{ name: format!("LD D, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::D)] },
-            0x1E => Instruction{ name: format!("LD E, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::E)] },
-            0x11 => Instruction{ name: format!("LD DE, 0x{:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::E), MicroInstr::LDRN(Register::D)] },
-            0x21 => Instruction{ name: format!("LD HL, 0x{:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::L), MicroInstr::LDRN(Register::H)] },
-            0x01 => Instruction{ name: format!("LD BC, 0x{:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::C), MicroInstr::LDRN(Register::B)] },
+            0x26 => Instruction{ name: format!("LD H, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::H)] },
+            0x0E => Instruction{ name: format!("LD C, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::C)] },
+            0x06 => Instruction{ name: format!("LD B, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::B)] },
+            0x2E => Instruction{ name: format!("LD L, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::L)] },
+            0x16 => Instruction{ name: format!("LD D, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::D)] },
+            0x1E => Instruction{ name: format!("LD E, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::E)] },
+            0x11 => Instruction{ name: format!("LD DE, 0x{:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::E), MicroInstr::LDRN(Register::D)] },
+            0x21 => Instruction{ name: format!("LD HL, 0x{:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::L), MicroInstr::LDRN(Register::H)] },
+            0x01 => Instruction{ name: format!("LD BC, 0x{:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::C), MicroInstr::LDRN(Register::B)] },
             0x47 => Instruction{ name: format!("LD B, A: 0x{:02X}", self.registers[Register::A]), steps: vec![MicroInstr::LDRR(Register::B, Register::A)] },
             0x78 => Instruction{ name: format!("LD A, B: 0x{:02X}", self.registers[Register::B]), steps: vec![MicroInstr::LDRR(Register::A, Register::B)] },
             0x7D => Instruction{ name: format!("LD A, L: 0x{:02X}", self.registers[Register::L]), steps: vec![MicroInstr::LDRR(Register::A, Register::L)] },
@@ -77,7 +77,7 @@ impl CPU {
             0x74 => Instruction{ name: format!("LD (0x{:04X}), H: 0x{:02X}", self.registers.get_hl(), self.registers[Register::H]), steps: vec![MicroInstr::NOP, MicroInstr::LDNNR(self.registers.get_hl(), Register::H, false)] }, 
             0x75 => Instruction{ name: format!("LD (0x{:04X}), L: 0x{:02X}", self.registers.get_hl(), self.registers[Register::L]), steps: vec![MicroInstr::NOP, MicroInstr::LDNNR(self.registers.get_hl(), Register::L, false)] }, 
             0x02 => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", self.registers.get_bc(), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::LDNNR(self.registers.get_bc(), Register::A, false)] }, 
-            0x36 => Instruction{ name: format!("LD (0x{:04X}), 0x{:02X}", self.registers.get_hl(), self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDHLN]},
+            0x36 => Instruction{ name: format!("LD (0x{:04X}), 0x{:02X}", self.registers.get_hl(), self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDHLN]},
             0x1A => Instruction{ name: format!("LD A, (0x{:04X})", self.registers.get_de()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::A, self.registers.get_de(), false)] }, 
             0x46 => Instruction{ name: format!("LD B, (0x{:04X})", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::B, self.registers.get_hl(), false)] }, 
             0x4E => Instruction{ name: format!("LD C, (0x{:04X})", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::C, self.registers.get_hl(), false)] }, 
@@ -87,34 +87,34 @@ impl CPU {
             0x5E => Instruction{ name: format!("LD E, (0x{:04X})", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::E, self.registers.get_hl(), false)] }, 
             0x66 => Instruction{ name: format!("LD H, (0x{:04X})", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::H, self.registers.get_hl(), false)] }, 
             0x0A => Instruction{ name: format!("LD A, (0x{:04X})", self.registers.get_bc()), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::A, self.registers.get_bc(), false)] }, 
-            0x31 => Instruction{ name: format!("LD SP, 0x{:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDSPNN]}, // 
-            0x08 => Instruction{ name: format!("LD (0x{:04X}), SP: 0x{:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16), self.sp), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDNNSP(Byte::LSB), MicroInstr::LDNNSP(Byte::MSB)] },
+            0x31 => Instruction{ name: format!("LD SP, 0x{:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDSPNN]}, // 
+            0x08 => Instruction{ name: format!("LD (0x{:04X}), SP: 0x{:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16), self.sp), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDNNSP(Byte::LSB), MicroInstr::LDNNSP(Byte::MSB)] },
             0xF9 => Instruction{ name: format!("LD SP, HL: 0x{:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::LDSPHL]},
             0xF8 => Instruction{ name: format!("LD HL, SP+i8"), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDHLSPN]},
-            0xEA => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDNNR(0, Register::A, false)]},
-            0x3E => Instruction{ name: format!("LD A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::A)]},
-            0xE0 => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", 0xFF00 | (self.bus.read(self.pc) as u16), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDNNR(0xFF00, Register::A, true)]},
+            0xEA => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDNNR(0, Register::A, false)]},
+            0x3E => Instruction{ name: format!("LD A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::NOP, MicroInstr::LDRN(Register::A)]},
+            0xE0 => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", 0xFF00 | (self.bus.peek(self.pc) as u16), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDNNR(0xFF00, Register::A, true)]},
             0xE2 => Instruction{ name: format!("LD (0x{:04X}), A: 0x{:02X}", 0xFF00 | (self.registers[Register::C] as u16), self.registers[Register::A]), steps: vec![MicroInstr::NOP, MicroInstr::LDNNR(0xFF00 + (self.registers[Register::C] as u16), Register::A, false)]},
-            0xF0 => Instruction{ name: format!("LD A, (0x{:04X})", 0xFF00 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDRNN(Register::A, 0xFF00, true)]},
+            0xF0 => Instruction{ name: format!("LD A, (0x{:04X})", 0xFF00 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::LDRNN(Register::A, 0xFF00, true)]},
             0xF2 => Instruction{ name: format!("LD A, (0x{:04X})", 0xFF00 | (self.registers[Register::C] as u16)), steps: vec![MicroInstr::NOP, MicroInstr::LDRNN(Register::A, 0xFF00 + (self.registers[Register::C] as u16), false)]},
-            0xFA => Instruction{ name: format!("LD A, (0x{:04X})", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDRNN(Register::A, 0, false)]},
+            0xFA => Instruction{ name: format!("LD A, (0x{:04X})", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::LDRNN(Register::A, 0, false)]},
 
             0x18 => Instruction{ name: format!("JR i8"), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::NOP, MicroInstr::JR]},
             0x20 => Instruction{ name: format!("JR NZ, i8"), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Cond(Flag::Z, false), MicroInstr::JR]},
             0x30 => Instruction{ name: format!("JR NC, i8"), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Cond(Flag::C, false), MicroInstr::JR]},
             0x38 => Instruction{ name: format!("JR C, i8"), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Cond(Flag::C, true), MicroInstr::JR]},
             0x28 => Instruction{ name: format!("JR Z, i8"), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Cond(Flag::Z, true), MicroInstr::JR]},
-            0xC3 => Instruction{ name: format!("JP ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::NOP, MicroInstr::JP]},
-            0xC2 => Instruction{ name: format!("JP NZ, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, false), MicroInstr::JP]},
-            0xCA => Instruction{ name: format!("JP Z, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, true), MicroInstr::JP]},
-            0xD2 => Instruction{ name: format!("JP NC, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, false), MicroInstr::JP]},
-            0xDA => Instruction{ name: format!("JP C, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, true), MicroInstr::JP]},
+            0xC3 => Instruction{ name: format!("JP ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::NOP, MicroInstr::JP]},
+            0xC2 => Instruction{ name: format!("JP NZ, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, false), MicroInstr::JP]},
+            0xCA => Instruction{ name: format!("JP Z, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, true), MicroInstr::JP]},
+            0xD2 => Instruction{ name: format!("JP NC, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, false), MicroInstr::JP]},
+            0xDA => Instruction{ name: format!("JP C, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, true), MicroInstr::JP]},
             0xE9 => Instruction{ name: format!("JP ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::JPHL] },
-            0xCD => Instruction{ name: format!("CALL ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
-            0xC4 => Instruction{ name: format!("CALL NZ, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, false), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]}, // CALL NZ,u16
-            0xCC => Instruction{ name: format!("CALL Z, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, true), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
-            0xD4 => Instruction{ name: format!("CALL NC, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, false), MicroInstr::PUSH(((self.pc + 2) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
-            0xDC => Instruction{ name: format!("CALL C, ${:04X}", (self.bus.read(self.pc + 1) as u16) << 8 | (self.bus.read(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, true), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
+            0xCD => Instruction{ name: format!("CALL ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::NOP, MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
+            0xC4 => Instruction{ name: format!("CALL NZ, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, false), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]}, // CALL NZ,u16
+            0xCC => Instruction{ name: format!("CALL Z, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::Z, true), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
+            0xD4 => Instruction{ name: format!("CALL NC, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, false), MicroInstr::PUSH(((self.pc + 2) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
+            0xDC => Instruction{ name: format!("CALL C, ${:04X}", (self.bus.peek(self.pc + 1) as u16) << 8 | (self.bus.peek(self.pc) as u16)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::Read(Byte::MSB), MicroInstr::Cond(Flag::C, true), MicroInstr::PUSH(((0xFF00 & (self.pc + 2)) >> 8) as u8), MicroInstr::PUSH((0x00FF & (self.pc + 2)) as u8), MicroInstr::JP]},
             0xC9 => Instruction{ name: format!("RET"), steps: vec![MicroInstr::NOP, MicroInstr::POPPC(Byte::LSB), MicroInstr::POPPC(Byte::MSB), MicroInstr::JP] },
             0xD0 => Instruction{ name: format!("RET NC"), steps: vec![MicroInstr::NOP, MicroInstr::Cond(Flag::C, false), MicroInstr::POPPC(Byte::LSB), MicroInstr::POPPC(Byte::MSB), MicroInstr::JP] },
             0xC8 => Instruction{ name: format!("RET Z"), steps: vec![MicroInstr::NOP, MicroInstr::Cond(Flag::Z, true), MicroInstr::POPPC(Byte::LSB), MicroInstr::POPPC(Byte::MSB), MicroInstr::JP] },
@@ -164,7 +164,7 @@ impl CPU {
             0xB4 => Instruction{ name: format!("OR A, H"), steps: vec![MicroInstr::OR(Register::H)] },
             0xB5 => Instruction{ name: format!("OR A, L"), steps: vec![MicroInstr::OR(Register::L)] },
             0xB6 => Instruction{ name: format!("OR A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::ORHL] },
-            0xF6 => Instruction{ name: format!("OR A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ORN] },
+            0xF6 => Instruction{ name: format!("OR A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ORN] },
 
             0xAF => Instruction{ name: format!("XOR A, A"), steps: vec![MicroInstr::XOR(Register::A)] },
             0xA9 => Instruction{ name: format!("XOR A, C"), steps: vec![MicroInstr::XOR(Register::C)] },
@@ -173,7 +173,7 @@ impl CPU {
             0xAA => Instruction{ name: format!("XOR A, D"), steps: vec![MicroInstr::XOR(Register::D)] },
             0xAB => Instruction{ name: format!("XOR A, E"), steps: vec![MicroInstr::XOR(Register::E)] },
             0xAC => Instruction{ name: format!("XOR A, H"), steps: vec![MicroInstr::XOR(Register::H)] },
-            0xEE => Instruction{ name: format!("XOR A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::XORN]},
+            0xEE => Instruction{ name: format!("XOR A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::XORN]},
             0xAE => Instruction{ name: format!("XOR A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::XORHL]},
 
             0xA0 => Instruction{ name: format!("AND A,B"), steps: vec![MicroInstr::AND(Register::B)] },
@@ -184,7 +184,7 @@ impl CPU {
             0xA5 => Instruction{ name: format!("AND A,L"), steps: vec![MicroInstr::AND(Register::L)] },
             0xA7 => Instruction{ name: format!("AND A,A"), steps: vec![MicroInstr::AND(Register::A)] },
             0xA6 => Instruction{ name: format!("AND A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::ANDHL]},
-            0xE6 => Instruction{ name: format!("AND A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ANDN]},
+            0xE6 => Instruction{ name: format!("AND A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ANDN]},
 
             0xBB => Instruction{ name: format!("CP A, E"), steps: vec![MicroInstr::CP(Register::E)] },
             0xBA => Instruction{ name: format!("CP A, D"), steps: vec![MicroInstr::CP(Register::D)] },
@@ -194,7 +194,7 @@ impl CPU {
             0xBD => Instruction{ name: format!("CP A, L"), steps: vec![MicroInstr::CP(Register::L)] },
             0xBF => Instruction{ name: format!("CP A, A"), steps: vec![MicroInstr::CP(Register::A)] },
             0xBE => Instruction{ name: format!("CP A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::CPHL]},
-            0xFE => Instruction{ name: format!("CP A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::CPN]},
+            0xFE => Instruction{ name: format!("CP A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::CPN]},
 
             0x80 => Instruction{ name: format!("ADD A, B"), steps: vec![MicroInstr::ADD(Register::B)] }, 
             0x81 => Instruction{ name: format!("ADD A, C"), steps: vec![MicroInstr::ADD(Register::C)] }, 
@@ -204,7 +204,7 @@ impl CPU {
             0x85 => Instruction{ name: format!("ADD A, L"), steps: vec![MicroInstr::ADD(Register::L)] }, 
             0x87 => Instruction{ name: format!("ADD A, A"), steps: vec![MicroInstr::ADD(Register::A)] }, 
             0x86 => Instruction{ name: format!("ADD A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::ADDHL] },
-            0xC6 => Instruction{ name: format!("ADD A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ADDN]},
+            0xC6 => Instruction{ name: format!("ADD A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ADDN]},
             0x29 => Instruction{ name: format!("ADD HL, HL"), steps: vec![MicroInstr::NOP, MicroInstr::ADDHLNN(self.registers.get_hl())] }, 
             0x09 => Instruction{ name: format!("ADD HL, BC"), steps: vec![MicroInstr::NOP, MicroInstr::ADDHLNN(self.registers.get_bc())] }, 
             0x19 => Instruction{ name: format!("ADD HL, DE"), steps: vec![MicroInstr::NOP, MicroInstr::ADDHLNN(self.registers.get_de())] }, 
@@ -219,7 +219,7 @@ impl CPU {
             0x8D => Instruction{ name: format!("ADC A, L"), steps: vec![MicroInstr::ADC(Register::L)] }, 
             0x8F => Instruction{ name: format!("ADC A, A"), steps: vec![MicroInstr::ADC(Register::A)] }, 
             0x8E => Instruction{ name: format!("ADC A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::ADCHL] },
-            0xCE => Instruction{ name: format!("ADC A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ADCN] },
+            0xCE => Instruction{ name: format!("ADC A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::ADCN] },
 
             0x90 => Instruction{ name: format!("SUB A, B"), steps: vec![MicroInstr::SUB(Register::B)] }, 
             0x91 => Instruction{ name: format!("SUB A, C"), steps: vec![MicroInstr::SUB(Register::C)] }, 
@@ -229,7 +229,7 @@ impl CPU {
             0x95 => Instruction{ name: format!("SUB A, L"), steps: vec![MicroInstr::SUB(Register::L)] }, 
             0x97 => Instruction{ name: format!("SUB A, A"), steps: vec![MicroInstr::SUB(Register::A)] }, 
             0x96 => Instruction{ name: format!("SUB A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::SUBHL] },
-            0xD6 => Instruction{ name: format!("SUB A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::SUBN] },
+            0xD6 => Instruction{ name: format!("SUB A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::SUBN] },
 
             0x98 => Instruction{ name: format!("SBC A, B"), steps: vec![MicroInstr::SBC(Register::B)] }, 
             0x99 => Instruction{ name: format!("SBC A, C"), steps: vec![MicroInstr::SBC(Register::C)] }, 
@@ -239,7 +239,7 @@ impl CPU {
             0x9D => Instruction{ name: format!("SBC A, L"), steps: vec![MicroInstr::SBC(Register::L)] }, 
             0x9F => Instruction{ name: format!("SBC A, A"), steps: vec![MicroInstr::SBC(Register::A)] }, 
             0x9E => Instruction{ name: format!("SBC A, ${:04X}", self.registers.get_hl()), steps: vec![MicroInstr::NOP, MicroInstr::SBCHL] },
-            0xDE => Instruction{ name: format!("SBC A, 0x{:02X}", self.bus.read(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::SBCN] },
+            0xDE => Instruction{ name: format!("SBC A, 0x{:02X}", self.bus.peek(self.pc)), steps: vec![MicroInstr::Read(Byte::LSB), MicroInstr::SBCN] },
 
             0xF5 => Instruction{ name: format!("PUSH AF"), steps: vec![MicroInstr::NOP, MicroInstr::NOP, MicroInstr::PUSH(self.registers[Register::A]), MicroInstr::PUSH(self.registers[Register::F])] }, 
             0xE5 => Instruction{ name: format!("PUSH HL"), steps: vec![MicroInstr::NOP, MicroInstr::NOP, MicroInstr::PUSH(self.registers[Register::H]), MicroInstr::PUSH(self.registers[Register::L])] }, 
@@ -268,7 +268,7 @@ impl CPU {
             _ => panic!("Unexpected opcode encountered 0x{:02X}", opcode)
         };
 
-        // let line = format!("{} ~ PC: 0x{:04X} IF: 0b{:08b} IE: 0b{:08b} IME: {} STAT: 0b{:08b}", instruction.name, self.pc - 1, self.bus.IF, self.bus.IE, self.ime, self.bus.read(0xFF41));
+        // let line = format!("{} ~ PC: 0x{:04X} IF: 0b{:08b} IE: 0b{:08b} IME: {} STAT: 0b{:08b}", instruction.name, self.pc - 1, self.bus.IF, self.bus.IE, self.ime, self.bus.peek(0xFF41));
         // console_log!("{}", line);
 
         // println!("{}", line);
@@ -1,6 +1,12 @@
 use crate::internal::ppu::{PPU, Display};
+use crate::internal::ppu::color::{ColorFormat, PaletteError};
+use crate::internal::bess::BessError;
+use crate::internal::state::{check, StateReader, StateWriter};
+use crate::internal::ppu::viewer::SpriteAttributes;
 use crate::internal::timer::Timer;
 use crate::internal::apu::APU;
+use crate::internal::timeline::{Timeline, EventKind};
+use crate::internal::cheats::Cheats;
 use crate::{u32_to_little_endian, console_log, log};
 
 const MBC_TYPE: usize = 0x0147;
@@ -16,9 +22,26 @@ enum MemoryBank {
     MBCNONE, MBC1, MBC1M, MBC3, MBC5
 }
 
+// decoded by Memory::load_state, nothing is applied until the whole save state has been read
+pub struct BusState {
+    registers: [u8; 3], // IE, IF, JOYP
+    mbc_ram_enabled: bool,
+    banking_mode: BankingMode,
+    banks: [u8; 3], // ROM bank, MBC5 ROM bank top bit, RAM bank
+    timer: Timer,
+    ppu: Box<PPU>
+}
+
+pub struct RamState {
+    wram: [u8; 0x2000],
+    hram: [u8; 0x7F],
+    sram: Vec<u8>
+}
+
 pub struct Memory {
     // testing
-    pub flat_ram: bool,
+    pub flat_ram: bool, // the whole address space is plain RAM (for the jsmoo tests)
+    flat_memory: Vec<u8>,
 
     // used for save files
     pub bess_buffer_offsets: Vec<u8>, 
@@ -44,8 +67,16 @@ pub struct Memory {
     joyp: u8,
 
     ppu: PPU,
+    framebuffer: Vec<u8>, // last frame converted to the selected color format, JS reads it straight out of wasm memory
     //apu: APU,
-    pub timer: Timer
+    pub timer: Timer,
+
+    pub timeline: Timeline,
+    pub cheats: Cheats,
+
+    // the PPU and timer are only stepped when something observable can happen (or their registers get accessed)
+    pending_cycles: usize,
+    cycles_to_event: usize
 }
 
 impl Memory {
@@ -53,6 +84,11 @@ impl Memory {
                                       0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
                                       0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E];
 
+    // cartridge types (0x147) load_cartridge has an MBC for
+    pub fn is_supported_cartridge(cartridge_type: u8) -> bool {
+        matches!(cartridge_type, 0x00..=0x03 | 0x0F..=0x13 | 0x19..=0x1E)
+    }
+
     pub fn load_cartridge(&mut self, bytes: Vec<u8>) {
         self.rom_chip = bytes;
 
@@ -106,17 +142,53 @@ impl Memory {
         info
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
+    // GameShark codes, called once per frame
+    pub fn apply_cheats(&mut self) {
+        let cheats = std::mem::take(&mut self.cheats);
+        for (bank, addr, value) in cheats.ram_writes() {
+            match bank {
+                Some(bank) => {
+                    let offset = (bank as usize * 0x2000) + (addr & 0x1FFF) as usize;
+                    if offset < self.sram.len() {
+                        self.sram[offset] = value;
+                    }
+                },
+                None => self.write(addr, value)
+            }
+        }
+        self.cheats = cheats;
+    }
+
+    pub fn get_rom(&self) -> &[u8] {
+        &self.rom_chip
+    }
+
+    pub fn get_rom_checksum(&self) -> u16 { // global checksum from the header, stored big endian
+        ((self.rom_chip[0x14E] as u16) << 8) | (self.rom_chip[0x14F] as u16)
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if !self.flat_ram && Memory::is_component_addr(addr) {
+            self.catch_up();
+        }
+        self.peek(addr)
+    }
+
+    // read without bringing the PPU and timer up to date first (disassembly, debugging)
+    pub fn peek(&self, addr: u16) -> u8 {
+        if self.flat_ram {
+            return self.flat_memory[addr as usize]
+        }
+
         match addr {
             0x0000..=0x7FFF => {
-                if self.memory_bank == MemoryBank::MBC1 {
-                    return self.mbc1_read(addr);
-                } else if self.memory_bank == MemoryBank::MBC3 {
-                    return self.mbc3_read(addr);
-                } else if self.memory_bank == MemoryBank::MBC5 {
-                    return self.mbc5_read(addr);
+                let val = match self.memory_bank {
+                    MemoryBank::MBC1 => self.mbc1_read(addr),
+                    MemoryBank::MBC3 => self.mbc3_read(addr),
+                    MemoryBank::MBC5 => self.mbc5_read(addr),
+                    _ => self.rom_chip[addr as usize]
                 };
-                self.rom_chip[addr as usize]
+                self.cheats.patch_rom(addr, val) // Game Genie
             },
             0xA000..=0xBFFF => {
                 if self.memory_bank == MemoryBank::MBC1 {
@@ -169,6 +241,14 @@ impl Memory {
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
+        if self.flat_ram {
+            self.flat_memory[addr as usize] = val;
+            return
+        }
+        if Memory::is_component_addr(addr) {
+            self.catch_up();
+        }
+
         match addr {
             0x0000..=0x7FFF => {
                 if self.memory_bank == MemoryBank::MBC1 {
@@ -195,14 +275,26 @@ impl Memory {
             0xFF04..=0xFF07 => self.timer.write_registers(addr, val),
             0xFF0F => self.IF = val,
             //0xFF10..=0xFF3F => self.apu.write_registers(addr, val),
-            0xFF46 => self.oam_dma_transfer((val as u16) << 8),
-            0xFF40..=0xFF4B => self.ppu.write_registers(addr, val),
+            0xFF46 => {
+                self.record_event(EventKind::OamDma(val));
+                self.oam_dma_transfer((val as u16) << 8)
+            },
+            0xFF40..=0xFF4B => {
+                if matches!(addr, 0xFF40..=0xFF43 | 0xFF45 | 0xFF4A | 0xFF4B) {
+                    self.record_event(EventKind::RegisterWrite(addr, val));
+                }
+                self.ppu.write_registers(addr, val)
+            },
             0xFF50 => (),
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = val, // High RAM (HRAM)
             0xFFFF => self.IE = val,
 
             _ => ()
         }
+
+        if Memory::is_component_addr(addr) { // the write can move the next event (LCD on, TAC/TIMA/TMA/DIV changes)
+            self.schedule_next_event();
+        }
     }
 
     fn oam_dma_transfer(&mut self, source: u16) {
@@ -334,7 +426,8 @@ impl Memory {
             0xA000..=0xBFFF => {
                 if self.mbc_ram_enabled {
                     let offset = ((self.ram_rom_bank_number as u32) << 13) | ((addr as u32) & 0x1FFF);
-                    self.sram[offset as usize] = val;
+                    let sram_len = self.sram.len() - 1;
+                    self.sram[(offset as usize) & sram_len] = val;
                 }
             }
             0x6000..=0x7FFF => (), // region not mapped in MBC5
@@ -353,6 +446,82 @@ impl Memory {
         }
     }
 
+    // WRAM, HRAM and SRAM are left to the BESS buffers
+    pub fn save_state(&mut self, state: &mut StateWriter) {
+        self.catch_up(); // nothing is left pending so the scheduler doesn't have to be saved
+
+        state.write_bytes(&[self.IE, self.IF, self.joyp]);
+        state.write_bool(self.mbc_ram_enabled);
+        state.write_bool(self.banking_mode == BankingMode::ADVANCED);
+        state.write_bytes(&[self.rom_bank_number, self.mbc5_rom_bank_number_top_bit, self.ram_rom_bank_number]);
+        self.timer.save_state(state);
+        self.ppu.save_state(state);
+    }
+
+    // WRAM, HRAM and SRAM for snapshots that don't go through a BESS file
+    pub fn save_ram(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.hram);
+        state.write_bytes(&self.sram);
+    }
+
+    pub fn load_ram(&self, state: &mut StateReader) -> Result<RamState, BessError> {
+        Ok(RamState {
+            wram: state.read_bytes(0x2000)?.try_into().unwrap(),
+            hram: state.read_bytes(0x7F)?.try_into().unwrap(),
+            sram: state.read_bytes(self.sram.len())?.to_vec()
+        })
+    }
+
+    pub fn restore_ram(&mut self, ram: RamState) {
+        self.wram = ram.wram;
+        self.hram = ram.hram;
+        self.sram = ram.sram;
+    }
+
+    pub fn load_state(&self, state: &mut StateReader) -> Result<BusState, BessError> {
+        let registers = state.read_bytes(3)?.try_into().unwrap();
+        let mbc_ram_enabled = state.read_bool()?;
+        let banking_mode = if state.read_bool()? { BankingMode::ADVANCED } else { BankingMode::SIMPLE };
+        let banks: [u8; 3] = state.read_bytes(3)?.try_into().unwrap();
+        check(self.memory_bank != MemoryBank::MBC1 || banks[2] <= 0x03, "RAM bank")?; // MBC1 indexes SRAM with it unmasked
+
+        Ok(BusState {
+            registers,
+            mbc_ram_enabled,
+            banking_mode,
+            banks,
+            timer: Timer::load_state(state)?,
+            ppu: PPU::load_state(state)?
+        })
+    }
+
+    pub fn restore_state(&mut self, bus: BusState) {
+        [self.IE, self.IF, self.joyp] = bus.registers;
+        self.mbc_ram_enabled = bus.mbc_ram_enabled;
+        self.banking_mode = bus.banking_mode;
+        [self.rom_bank_number, self.mbc5_rom_bank_number_top_bit, self.ram_rom_bank_number] = bus.banks;
+        self.timer = bus.timer;
+        self.ppu.restore_state(bus.ppu);
+
+        self.pending_cycles = 0;
+        self.cycles_to_event = 1;
+    }
+
+    // copies a BESS buffer straight into memory so the PPU mode can't block it, anything past the DMG's size (CGB RAM banks) is cut off
+    pub fn load_buffer(&mut self, base: u16, buffer: &[u8]) {
+        let memory: &mut [u8] = match base {
+            0xC000 => &mut self.wram,
+            0x8000 => &mut self.ppu.vram,
+            0xA000 => &mut self.sram,
+            0xFE00 => &mut self.ppu.oam,
+            0xFF80 => &mut self.hram,
+            _ => panic!("no buffer at 0x{:04X}", base)
+        };
+        let len = buffer.len().min(memory.len());
+        memory[..len].copy_from_slice(&buffer[..len]);
+    }
+
     pub fn aggregate_buffers(&mut self) -> Vec<u8> {
         let mut buffers = vec![];
 
@@ -380,47 +549,163 @@ impl Memory {
     }
 
     pub fn update_requested_interrupts(&mut self) {
-        let mut requests: u8 = 0x0;
-
-        if !self.ppu.vblank_irq_triggered && (self.ppu.read_registers(0xFF41) & 0x3 == 1) { // VBLANK interrupt
-            requests |= 0b00000001;
-            self.ppu.vblank_irq_triggered = true;
-        }
+        let mut requests: u8 = self.ppu.take_requested_interrupts(); // VBLANK and STAT
 
-        if !self.ppu.stat_irq_triggered {
-            if ((self.ppu.read_registers(0xFF41) >> 6) & 0x1 == 1) && (self.ppu.read_registers(0xFF41) >> 2 & 0x1 == 1) { requests |= 0b00000010; } // STAT interrupt (LY == LYC)
-            if ((self.ppu.read_registers(0xFF41) >> 5) & 0x1 == 1) && (self.ppu.read_registers(0xFF41) & 0x3 == 2) { requests |= 0b00000010 }; // STAT interrupt (OAM)
-            if ((self.ppu.read_registers(0xFF41) >> 4) & 0x1 == 1) && (self.ppu.read_registers(0xFF41) & 0x3 == 1) { requests |= 0b00000010 }; // STAT interrupt (VBLANK)
-            if ((self.ppu.read_registers(0xFF41) >> 3) & 0x1 == 1) && (self.ppu.read_registers(0xFF41) & 0x3 == 0) { requests |= 0b00000010 }; // STAT interrupt (HBLANK)
-            self.ppu.stat_irq_triggered = true;
+        if self.timer.tima_irq {
+            self.timer.tima_irq = false;
+            requests |= 0b00000100; // TIMER interrupt
         }
 
-        if self.timer.tima_irq > 0 { // starts at 2 to delay 1 cycle
-            self.timer.tima_irq -= 1;
-            if self.timer.tima_irq == 0 { 
-                requests |= 0b00000100; // TIMER interrupt
+        for i in 0..5 {
+            if (requests >> i) & 0x1 == 1 {
+                self.record_event(EventKind::InterruptRequested(i));
             }
         }
 
         self.IF |= requests | 0xE0;
     }
 
+    pub fn pending_interrupts(&self) -> u8 { // requested and enabled
+        self.IE & self.IF & 0x1F
+    }
+
+    pub fn record_event(&mut self, kind: EventKind) {
+        let (ly, dot) = self.ppu.get_position();
+        self.timeline.record(ly, dot, kind);
+    }
+
     pub fn update_components(&mut self) { // 1 M-cycle
-        self.ppu.update();
-        self.timer.update();
+        self.timeline.cycle += 4;
+        self.pending_cycles += 1;
+        if self.pending_cycles >= self.cycles_to_event {
+            self.catch_up();
+        }
         // self.apu.update(((self.timer.sysclock >> 12) & 0x1) as u8); // bit 4 of DIV register
     }
 
+    fn is_component_addr(addr: u16) -> bool { // VRAM, OAM, timer, IF and LCD registers
+        matches!(addr, 0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF04..=0xFF07 | 0xFF0F | 0xFF40..=0xFF4B)
+    }
+
+    // runs the PPU and timer for every cycle that has passed since they were last brought up to date
+    pub fn catch_up(&mut self) {
+        if self.timeline.enabled { // events have to be stamped with the cycle they happened on
+            for _ in 0..self.pending_cycles {
+                let mode = self.ppu.get_mode();
+                self.ppu.update();
+                self.timer.update();
+
+                if self.ppu.get_mode() != mode {
+                    self.record_event(EventKind::ModeChanged(self.ppu.get_mode()));
+                }
+                if self.timer.is_reloading() { // TIMA has just been reloaded after overflowing
+                    self.record_event(EventKind::TimerOverflow);
+                }
+            }
+        } else {
+            self.ppu.run(self.pending_cycles);
+            self.timer.run(self.pending_cycles);
+        }
+
+        self.pending_cycles = 0;
+        self.update_requested_interrupts(); // IF reads see requests from the cycles just run
+        self.schedule_next_event();
+    }
+
+    fn schedule_next_event(&mut self) {
+        self.cycles_to_event = if self.timeline.enabled { 1 } else { self.ppu.cycles_until_event().min(self.timer.cycles_until_event()) };
+    }
+
+    pub fn set_timeline_enabled(&mut self, enabled: bool) {
+        self.catch_up();
+        self.timeline.enabled = enabled;
+        self.cycles_to_event = 1;
+    }
+
+    pub fn set_renderer(&mut self, renderer: u8) {
+        self.catch_up();
+        self.ppu.set_renderer(renderer);
+    }
+
     pub fn get_display(&self) -> Display {
         self.ppu.lcd
     }
 
+    pub fn get_display_sources(&self) -> Display {
+        self.ppu.lcd_source
+    }
+
+    pub fn to_rgba(&self, shades: &[u8], sources: &[u8]) -> Vec<u8> {
+        self.ppu.colors.to_rgba(shades, sources)
+    }
+
+    pub fn update_framebuffer(&mut self) {
+        if self.ppu.colors.format != ColorFormat::Shades { // raw shades are read straight from the LCD
+            self.ppu.colors.convert_into(&self.ppu.lcd, &self.ppu.lcd_source, &mut self.framebuffer);
+        }
+    }
+
+    pub fn get_framebuffer(&self) -> &[u8] {
+        match self.ppu.colors.format {
+            ColorFormat::Shades => &self.ppu.lcd,
+            _ => &self.framebuffer
+        }
+    }
+
+    pub fn set_color_format(&mut self, format: u8) {
+        self.ppu.colors.set_format(format);
+        self.update_framebuffer();
+    }
+
+    pub fn set_palette_preset(&mut self, preset: u8) {
+        self.ppu.colors.set_preset(preset);
+        self.update_framebuffer();
+    }
+
+    pub fn set_palette(&mut self, source: u8, colors: &[u32]) -> Result<(), PaletteError> {
+        self.ppu.colors.set_palette(source, colors)?;
+        self.update_framebuffer();
+        Ok(())
+    }
+
     pub fn get_debug_panel(&mut self) -> [usize; 144 * 3] {
         let old = self.ppu.debug_panel;
         self.ppu.debug_panel = [0; 144 * 3];
         return old;
     }
 
+    pub fn get_tile_sheet(&self, palette: u8) -> Vec<u8> {
+        self.ppu.tile_sheet(palette)
+    }
+
+    pub fn get_tile_map(&self, map: u8) -> Vec<u8> {
+        self.ppu.tile_map(map)
+    }
+
+    pub fn get_oam_sprites(&self) -> Vec<SpriteAttributes> {
+        self.ppu.oam_sprites()
+    }
+
+    pub fn get_oam_sheet(&self) -> Vec<u8> {
+        self.ppu.oam_sheet()
+    }
+
+    pub fn set_layer_visible(&mut self, layer: u8, visible: bool) {
+        self.ppu.layers.set_visible(layer, visible);
+    }
+
+    pub fn set_separate_layers(&mut self, separate: bool) {
+        self.ppu.layers.set_separate(separate);
+    }
+
+    pub fn get_layer(&self, layer: u8) -> Display {
+        self.ppu.layers.get(layer)
+    }
+
+    pub fn get_timeline_trace(&self) -> String {
+        self.timeline.to_chrome_trace()
+    }
+
     pub fn is_frame_rendered(&mut self) -> bool {
         if self.ppu.rendered_frame {
             self.ppu.rendered_frame = false;
@@ -439,12 +724,16 @@ impl Default for Memory {
             mbc_ram_enabled: false,
             boot_rom: [0x0; 0x100],
             ppu: PPU::default(),
+            framebuffer: vec![],
             IE: 0x0,
             IF: 0x0,
             joyp: 0x0,
             keypress: -1,
             timer: Timer::default(),
+            pending_cycles: 0,
+            cycles_to_event: 1,
             flat_ram: false,
+            flat_memory: vec![0x0; 0x10000],
             ram_rom_bank_number: 0x00,
             rom_bank_number: 0x00,
             hram: [0x0; 0x7F],
@@ -453,6 +742,8 @@ impl Default for Memory {
             //apu: APU::default(),
             bess_buffer_offsets: vec![],
             mbc5_rom_bank_number_top_bit: 0,
+            timeline: Timeline::default(),
+            cheats: Cheats::default(),
         }
     }
 }   
\ No newline at end of file
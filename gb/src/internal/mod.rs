@@ -2,4 +2,15 @@ pub mod memory;
 pub mod core;
 pub mod ppu;
 pub mod timer;
-pub mod apu;
\ No newline at end of file
+pub mod apu;
+pub mod timeline;
+pub mod state;
+pub mod bess;
+pub mod slots;
+pub mod rewind;
+pub mod checksum;
+pub mod movie;
+pub mod cheats;
+pub mod search;
+pub mod patch;
+pub mod archive;
\ No newline at end of file
@@ -0,0 +1,560 @@
+use std::fmt;
+use serde_json::json;
+use crate::internal::bess::BessError;
+use crate::internal::checksum::fnv1a;
+use crate::internal::core::component::CPU;
+use crate::internal::ppu::Display;
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const VERSION: u16 = 1;
+
+// BK2 input log columns and the keypress each one stands for
+const LOG_KEY: &str = "LogKey:#Up|Down|Left|Right|Start|Select|B|A|";
+const BUTTONS: [&str; 8] = ["Up", "Down", "Left", "Right", "Start", "Select", "B", "A"];
+const MNEMONICS: [char; 8] = ['U', 'D', 'L', 'R', 'S', 's', 'B', 'A'];
+const KEYPRESSES: [i8; 8] = [1, 3, 2, 4, 7, 8, 6, 5];
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    MissingMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidInput(usize), // frame
+    MultipleButtons(usize), // frame, only one key can be held at a time
+    InvalidHeader(String),
+    MissingInputLog,
+    WrongRom { movie: u16, rom: u16 },
+    Bess(BessError)
+}
+
+impl From<BessError> for MovieError {
+    fn from(err: BessError) -> Self {
+        MovieError::Bess(err)
+    }
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::MissingMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => write!(f, "movie was written by a newer version ({})", version),
+            MovieError::Truncated => write!(f, "movie file ended early"),
+            MovieError::InvalidInput(frame) => write!(f, "invalid input on frame {}", frame),
+            MovieError::MultipleButtons(frame) => write!(f, "more than one button held on frame {}", frame),
+            MovieError::InvalidHeader(line) => write!(f, "invalid header line {:?}", line),
+            MovieError::MissingInputLog => write!(f, "missing [Input] section"),
+            MovieError::WrongRom { movie, rom } => write!(f, "movie belongs to another ROM (checksum 0x{:04X}, loaded ROM is 0x{:04X})", movie, rom),
+            MovieError::Bess(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Movie {
+    pub rom_checksum: u16,
+    pub sync_interval: u32, // frames between sync hashes, 0 for none
+    pub save_file: Option<Vec<u8>>, // BESS state the movie starts from, power-on otherwise
+    pub inputs: Vec<i8>, // keypress of every frame
+    pub sync_hashes: Vec<u64> // display after frame (i + 1) * sync_interval
+}
+
+// bounds checked reads, movie files come from users
+struct Reader<'a> {
+    bytes: &'a [u8],
+    ptr: usize
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], MovieError> {
+        if len > self.bytes.len() - self.ptr {
+            return Err(MovieError::Truncated)
+        }
+        let bytes = &self.bytes[self.ptr..self.ptr + len];
+        self.ptr += len;
+        Ok(bytes)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, MovieError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, MovieError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, MovieError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+impl Movie {
+    pub fn new(rom_checksum: u16, sync_interval: u32, save_file: Option<Vec<u8>>) -> Self {
+        Self { rom_checksum, sync_interval, save_file, inputs: vec![], sync_hashes: vec![] }
+    }
+
+    // "GBMV" | version | ROM checksum | sync interval | state length + BESS state | frames + inputs | hashes + sync hashes
+    // inputs are one byte per frame, 0 when nothing is held
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        bytes.extend_from_slice(&self.sync_interval.to_le_bytes());
+
+        let save_file = self.save_file.as_deref().unwrap_or_default();
+        bytes.extend_from_slice(&(save_file.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(save_file);
+
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        bytes.extend(self.inputs.iter().map(|keypress| if *keypress == -1 { 0 } else { *keypress as u8 }));
+
+        bytes.extend_from_slice(&(self.sync_hashes.len() as u32).to_le_bytes());
+        for hash in &self.sync_hashes {
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader { bytes, ptr: 0 };
+        if reader.read_bytes(4) != Ok(MAGIC) {
+            return Err(MovieError::MissingMagic)
+        }
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version))
+        }
+        let rom_checksum = reader.read_u16()?;
+        let sync_interval = reader.read_u32()?;
+
+        let save_file_len = reader.read_u32()? as usize;
+        let save_file = reader.read_bytes(save_file_len)?;
+        let save_file = if save_file.is_empty() { None } else { Some(save_file.to_vec()) };
+
+        let frames = reader.read_u32()? as usize;
+        let mut inputs = Vec::with_capacity(frames.min(bytes.len()));
+        for (frame, input) in reader.read_bytes(frames)?.iter().enumerate() {
+            inputs.push(match input {
+                0 => -1,
+                1..=8 => *input as i8,
+                _ => return Err(MovieError::InvalidInput(frame))
+            });
+        }
+
+        let hashes = reader.read_u32()? as usize;
+        let mut sync_hashes = Vec::with_capacity(hashes.min(bytes.len() / 8));
+        for _ in 0..hashes {
+            sync_hashes.push(reader.read_u64()?);
+        }
+        Ok(Self { rom_checksum, sync_interval, save_file, inputs, sync_hashes })
+    }
+
+    // BK2 style: "Key value" header lines followed by the input log, one "|UDLRSsBA|" line per frame
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("Platform GB\n");
+        text += &format!("RomChecksum {:04X}\n", self.rom_checksum);
+        text += &format!("SyncInterval {}\n", self.sync_interval);
+        if !self.sync_hashes.is_empty() {
+            let hashes: Vec<_> = self.sync_hashes.iter().map(|hash| format!("{:016X}", hash)).collect();
+            text += &format!("SyncHashes {}\n", hashes.join(" "));
+        }
+        if let Some(save_file) = &self.save_file {
+            text += &format!("Savestate {}\n", to_hex(save_file));
+        }
+
+        text += "[Input]\n";
+        text += LOG_KEY;
+        text += "\n";
+        for keypress in &self.inputs {
+            let column = KEYPRESSES.iter().position(|key| key == keypress);
+            let buttons: String = (0..8).map(|i| if column == Some(i) { MNEMONICS[i] } else { '.' }).collect();
+            text += &format!("|{}|\n", buttons);
+        }
+        text += "[/Input]\n";
+        text
+    }
+
+    // unknown header keys and input columns (BizHawk logs a Power column) are skipped so BizHawk movies can be pasted in as is
+    pub fn from_text(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new(0, 0, None);
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        loop {
+            let line = lines.next().ok_or(MovieError::MissingInputLog)?;
+            if line == "[Input]" {
+                break
+            }
+            let invalid = || MovieError::InvalidHeader(String::from(line));
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "RomChecksum" => movie.rom_checksum = u16::from_str_radix(value, 16).map_err(|_| invalid())?,
+                "SyncInterval" => movie.sync_interval = value.parse().map_err(|_| invalid())?,
+                "SyncHashes" => {
+                    movie.sync_hashes = value.split_whitespace().map(|hash| u64::from_str_radix(hash, 16)).collect::<Result<_, _>>().map_err(|_| invalid())?;
+                },
+                "Savestate" => movie.save_file = Some(from_hex(value).ok_or_else(invalid)?),
+                _ => ()
+            }
+        }
+
+        // keypress of every column, the LogKey line (if any) says which button each one is
+        let mut columns: Vec<Option<i8>> = KEYPRESSES.iter().map(|keypress| Some(*keypress)).collect();
+        for line in lines {
+            if let Some(log_key) = line.strip_prefix("LogKey:") {
+                columns = log_key.split('|').filter(|name| !name.is_empty()).map(|name| {
+                    let name = name.trim_start_matches('#'); // starts a group of columns
+                    BUTTONS.iter().position(|button| *button == name).map(|column| KEYPRESSES[column])
+                }).collect();
+                continue
+            }
+            if line == "[/Input]" {
+                return Ok(movie)
+            }
+
+            let frame = movie.inputs.len();
+            let buttons: Vec<char> = line.chars().filter(|c| *c != '|').collect();
+            if !line.starts_with('|') || buttons.len() != columns.len() {
+                return Err(MovieError::InvalidInput(frame))
+            }
+            let mut held = buttons.iter().zip(&columns).filter(|(c, _)| **c != '.').filter_map(|(_, keypress)| *keypress);
+            movie.inputs.push(match (held.next(), held.next()) {
+                (None, _) => -1,
+                (Some(keypress), None) => keypress,
+                _ => return Err(MovieError::MultipleButtons(frame))
+            });
+        }
+        Err(MovieError::MissingInputLog)
+    }
+}
+
+#[derive(PartialEq)]
+enum Mode {
+    Idle,
+    Recording,
+    Playing
+}
+
+// sits between the frontend and CPU::next_frame, recording the input of every frame or feeding it back in
+// the movie frame is worked out from the core's frame counter so rewinding or loading a state while
+// recording cuts the movie off there, and while playing picks up from the matching frame
+pub struct MovieSession {
+    movie: Movie,
+    mode: Mode,
+    start_frame: u64, // core frame counter at the start of the movie
+    desync: Option<usize> // first frame whose sync hash didn't match
+}
+
+impl MovieSession {
+    // starts at power-on, or from the current state which then gets embedded in the movie
+    pub fn record(&mut self, core: &mut CPU, from_save_state: bool, sync_interval: u32) {
+        let save_file = if from_save_state {
+            Some(core.create_save_file())
+        } else {
+            core.power_on();
+            None
+        };
+        self.movie = Movie::new(core.bus.get_rom_checksum(), sync_interval, save_file);
+        self.mode = Mode::Recording;
+        self.start_frame = core.frame_count;
+        self.desync = None;
+    }
+
+    pub fn play(&mut self, core: &mut CPU, movie: Movie) -> Result<(), MovieError> {
+        let rom = core.bus.get_rom_checksum();
+        if movie.rom_checksum != rom {
+            return Err(MovieError::WrongRom { movie: movie.rom_checksum, rom })
+        }
+        match &movie.save_file {
+            Some(save_file) => core.load_save_file(save_file.clone())?,
+            None => core.power_on()
+        }
+        self.movie = movie;
+        self.mode = Mode::Playing;
+        self.start_frame = core.frame_count;
+        self.desync = None;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.mode = Mode::Idle;
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn next_frame(&mut self, core: &mut CPU, keypress: i8) -> Display {
+        if self.mode == Mode::Idle {
+            return core.next_frame(keypress)
+        }
+        let frame = match core.frame_count.checked_sub(self.start_frame) {
+            Some(frame) => frame as usize,
+            None => { // went back to before the movie started
+                self.mode = Mode::Idle;
+                return core.next_frame(keypress)
+            }
+        };
+        let interval = self.movie.sync_interval as usize;
+        let is_sync_frame = interval != 0 && (frame + 1).is_multiple_of(interval);
+
+        if self.mode == Mode::Recording {
+            let keypress = if (1..=8).contains(&keypress) { keypress } else { -1 };
+            self.movie.inputs.truncate(frame);
+            self.movie.inputs.push(keypress);
+
+            let display = core.next_frame(keypress);
+            if let Some(hashes) = frame.checked_div(interval) {
+                self.movie.sync_hashes.truncate(hashes);
+                if is_sync_frame {
+                    self.movie.sync_hashes.push(fnv1a(&display));
+                }
+            }
+            return display
+        }
+
+        if frame >= self.movie.inputs.len() {
+            self.mode = Mode::Idle;
+            return core.next_frame(keypress)
+        }
+        let display = core.next_frame(self.movie.inputs[frame]);
+        if is_sync_frame && self.desync.is_none() {
+            if let Some(hash) = self.movie.sync_hashes.get((frame + 1) / interval - 1) {
+                if *hash != fnv1a(&display) {
+                    self.desync = Some(frame + 1);
+                }
+            }
+        }
+        display
+    }
+
+    // { mode, frame, length, desync } where frame and desync count from the start of the movie
+    pub fn to_json(&self, core: &CPU) -> String {
+        let mode = match self.mode {
+            Mode::Idle => "idle",
+            Mode::Recording => "recording",
+            Mode::Playing => "playing"
+        };
+        json!({
+            "mode": mode,
+            "frame": core.frame_count.saturating_sub(self.start_frame),
+            "length": self.movie.inputs.len(),
+            "desync": self.desync
+        }).to_string()
+    }
+}
+
+impl Default for MovieSession {
+    fn default() -> Self {
+        Self {
+            movie: Movie::new(0, 0, None),
+            mode: Mode::Idle,
+            start_frame: 0,
+            desync: None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::internal::bess;
+
+    fn setup_core() -> CPU {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        core
+    }
+
+    // records `frames` frames of varying input and returns what was displayed
+    fn record(session: &mut MovieSession, core: &mut CPU, frames: usize) -> Vec<Display> {
+        (0..frames).map(|i| session.next_frame(core, [-1, 1, 5, 7, -1, 3][i % 6])).collect()
+    }
+
+    #[test]
+    fn movies_replay_bit_exactly() {
+        let mut core = setup_core();
+        for _ in 0..7 {
+            core.next_frame(-1);
+        }
+        let mut session = MovieSession::default();
+        session.record(&mut core, false, 10);
+        assert_eq!(core.frame_count, 0);
+        let displays = record(&mut session, &mut core, 45);
+        assert_eq!(session.movie().sync_hashes.len(), 4);
+        let movie = Movie::from_bytes(&session.movie().to_bytes()).unwrap();
+        assert_eq!(&movie, session.movie());
+
+        let mut fresh = setup_core(); // power_on is the same as booting
+        for (keypress, display) in movie.inputs.iter().zip(&displays) {
+            assert_eq!(fresh.next_frame(*keypress), *display);
+        }
+
+        let mut other = setup_core();
+        other.next_frame(-1);
+        let mut playback = MovieSession::default();
+        playback.play(&mut other, movie).unwrap();
+        for display in &displays {
+            assert_eq!(playback.next_frame(&mut other, 6), *display);
+        }
+        assert_eq!(playback.desync, None);
+        assert!(playback.mode == Mode::Playing);
+        playback.next_frame(&mut other, -1);
+        assert!(playback.mode == Mode::Idle);
+    }
+
+    #[test]
+    fn movies_can_start_from_a_save_state() {
+        let mut core = setup_core();
+        for _ in 0..15 {
+            core.next_frame(-1);
+        }
+        let mut session = MovieSession::default();
+        session.record(&mut core, true, 5);
+        let displays = record(&mut session, &mut core, 20);
+
+        let mut other = setup_core();
+        let mut playback = MovieSession::default();
+        playback.play(&mut other, Movie::from_text(&session.movie().to_text()).unwrap()).unwrap();
+        assert_eq!(other.frame_count, 15);
+        for display in &displays {
+            assert_eq!(playback.next_frame(&mut other, -1), *display);
+        }
+        assert_eq!(playback.desync, None);
+    }
+
+    #[test]
+    fn corrupted_save_state_leaves_the_game_running() {
+        let mut core = setup_core();
+        core.next_frame(-1);
+        let mut save_file = core.create_save_file();
+        let block = bess::parse(&save_file).unwrap().into_iter().find(|block| block.name == *b"EMUF").unwrap();
+        let offset = block.data.as_ptr() as usize - save_file.as_ptr() as usize;
+        save_file[offset + 2 + 13] = 0xFF; // EI delay, after the version, registers, PC, SP and IME
+
+        core.next_frame(-1);
+        let hash = core.state_hash();
+        let mut playback = MovieSession::default();
+        let movie = Movie::new(core.bus.get_rom_checksum(), 1, Some(save_file));
+        assert_eq!(playback.play(&mut core, movie).err(), Some(MovieError::Bess(BessError::InvalidState("EI delay"))));
+        assert!(playback.mode == Mode::Idle);
+        assert_eq!(core.state_hash(), hash);
+    }
+
+    #[test]
+    fn rewinding_while_recording_cuts_the_movie() {
+        let mut core = setup_core();
+        let mut session = MovieSession::default();
+        session.record(&mut core, false, 4);
+        record(&mut session, &mut core, 10);
+        let mut snapshot = vec![];
+        core.save_snapshot(&mut snapshot);
+        record(&mut session, &mut core, 10);
+        assert_eq!(session.movie().inputs.len(), 20);
+
+        core.load_snapshot(&snapshot).unwrap();
+        session.next_frame(&mut core, 8);
+        assert_eq!(session.movie().inputs.len(), 11);
+        assert_eq!(session.movie().inputs[10], 8);
+        assert_eq!(session.movie().sync_hashes.len(), 2);
+    }
+
+    #[test]
+    fn desyncs_are_reported() {
+        let mut core = setup_core();
+        let mut session = MovieSession::default();
+        session.record(&mut core, false, 10);
+        record(&mut session, &mut core, 30);
+
+        let mut movie = Movie::from_bytes(&session.movie().to_bytes()).unwrap();
+        movie.sync_hashes[1] ^= 1;
+        let mut playback = MovieSession::default();
+        playback.play(&mut core, movie).unwrap();
+        for _ in 0..30 {
+            playback.next_frame(&mut core, -1);
+        }
+        assert_eq!(playback.desync, Some(20));
+        assert_eq!(playback.to_json(&core), r#"{"desync":20,"frame":30,"length":30,"mode":"playing"}"#);
+
+        let mut other_rom = Movie::from_bytes(&session.movie().to_bytes()).unwrap();
+        other_rom.rom_checksum ^= 1;
+        assert!(matches!(playback.play(&mut core, other_rom), Err(MovieError::WrongRom { .. })));
+    }
+
+    #[test]
+    fn text_format() {
+        let mut movie = Movie::new(0xBEEF, 2, Some(vec![0xDE, 0xAD]));
+        movie.inputs = vec![-1, 1, 3, 2, 4, 7, 8, 6, 5];
+        movie.sync_hashes = vec![0x0123456789ABCDEF, 42];
+        let text = movie.to_text();
+        assert!(text.contains("[Input]\nLogKey:#Up|Down|Left|Right|Start|Select|B|A|\n|........|\n|U.......|\n|.D......|\n"));
+        assert_eq!(Movie::from_text(&text).unwrap(), movie);
+
+        let no_power = "MovieVersion BizHawk v2.0.0\nPlatform GB\n[Input]\nLogKey:#Up|Down|Left|Right|Start|Select|B|A|\n|......B.|\n[/Input]\n";
+        assert_eq!(Movie::from_text(no_power).unwrap().inputs, [6]);
+        assert_eq!(Movie::from_text("[Input]\n|........|\n|U......A|\n[/Input]"), Err(MovieError::MultipleButtons(1)));
+        assert_eq!(Movie::from_text("[Input]\n|....|\n[/Input]"), Err(MovieError::InvalidInput(0)));
+        assert_eq!(Movie::from_text("SyncInterval x\n[Input]\n[/Input]"), Err(MovieError::InvalidHeader(String::from("SyncInterval x"))));
+        assert_eq!(Movie::from_text("Platform GB\n"), Err(MovieError::MissingInputLog));
+    }
+
+    // Header.txt and Input Log.txt of a Gambatte BK2, pasted one after the other
+    const BK2: &str = "MovieVersion BizHawk v2.0.0
+Author 
+emuVersion Version 2.5.2
+OriginalEmuVersion Version 2.5.2
+Platform GB
+GameName Tetris (World) (Rev A)
+SHA1 74591CC9501AF93873F9A5D3EB12DA12C0723BBC
+BoardName MBC1 ROM
+Core Gambatte
+rerecordCount 12
+[Input]
+LogKey:#Up|Down|Left|Right|Start|Select|B|A|Power|
+|.........|
+|........P|
+|....S....|
+|.........|
+|..L.....P|
+|.......A.|
+[/Input]
+";
+
+    #[test]
+    fn bizhawk_input_log() {
+        assert_eq!(Movie::from_text(BK2).unwrap().inputs, [-1, -1, 7, -1, 2, 5]); // Power is ignored
+
+        // columns are matched by name, not position
+        let reordered = "[Input]\nLogKey:#Power|#A|B|Select|Start|Right|Left|Down|Up|\n|P.B......|\n|........U|\n[/Input]";
+        assert_eq!(Movie::from_text(reordered).unwrap().inputs, [6, 1]);
+        assert_eq!(Movie::from_text("[Input]\nLogKey:#Up|Down|Left|Right|Start|Select|B|A|Power|\n|......B.|\n[/Input]"), Err(MovieError::InvalidInput(0)));
+        assert_eq!(Movie::from_text("[Input]\nLogKey:#Up|Down|Left|Right|Start|Select|B|A|Power|\n|U......AP|\n[/Input]"), Err(MovieError::MultipleButtons(0)));
+    }
+
+    #[test]
+    fn binary_format_is_bounds_checked() {
+        let mut movie = Movie::new(0x1234, 1, None);
+        movie.inputs = vec![-1, 5, 5];
+        movie.sync_hashes = vec![1, 2, 3];
+        let bytes = movie.to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Movie::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut invalid = bytes.clone();
+        invalid[21] = 9; // second input
+        assert_eq!(Movie::from_bytes(&invalid), Err(MovieError::InvalidInput(1)));
+        assert_eq!(Movie::from_bytes(b"BESS"), Err(MovieError::MissingMagic));
+    }
+}
@@ -0,0 +1,385 @@
+use std::fmt;
+use crate::internal::checksum::crc32;
+
+pub const MAX_ROM_SIZE: usize = 0x800000; // 8 MiB, the most MBC5 can address
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,
+    OutOfBounds, // a copy reads outside the source ROM or the output so far
+    RomTooLarge(u64),
+    SourceSize { expected: u64, actual: usize },
+    SourceChecksum { expected: u32, actual: u32 }, // patch is for another ROM (or revision)
+    TargetChecksum { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, UPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch ended early"),
+            PatchError::OutOfBounds => write!(f, "patch copies from outside the ROM"),
+            PatchError::RomTooLarge(size) => write!(f, "patched ROM would be {} bytes", size),
+            PatchError::SourceSize { expected, actual } => write!(f, "patch expects a {} byte ROM, loaded ROM is {} bytes", expected, actual),
+            PatchError::SourceChecksum { expected, actual } => write!(f, "patch is for another ROM (CRC32 {:08X}, loaded ROM is {:08X})", expected, actual),
+            PatchError::TargetChecksum { expected, actual } => write!(f, "patched ROM has CRC32 {:08X} instead of {:08X}", actual, expected),
+            PatchError::PatchChecksum { expected, actual } => write!(f, "patch is corrupt (CRC32 {:08X} instead of {:08X})", actual, expected)
+        }
+    }
+}
+
+struct Reader<'a> {
+    patch: &'a [u8],
+    ptr: usize,
+    end: usize // start of the footer
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        if len > self.end - self.ptr {
+            return Err(PatchError::Truncated)
+        }
+        let bytes = &self.patch[self.ptr..self.ptr + len];
+        self.ptr += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<usize, PatchError> {
+        let bytes = self.bytes(2)?;
+        Ok(((bytes[0] as usize) << 8) | bytes[1] as usize)
+    }
+
+    fn u24_be(&mut self) -> Result<usize, PatchError> {
+        let bytes = self.bytes(3)?;
+        Ok(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+    }
+
+    // UPS/BPS variable length number, 7 bits per byte with the top bit set on the last one
+    fn number(&mut self) -> Result<u64, PatchError> {
+        let mut number: u64 = 0;
+        let mut shift: u64 = 1;
+        loop {
+            let byte = self.byte()?;
+            number = ((byte & 0x7F) as u64).checked_mul(shift).and_then(|bits| number.checked_add(bits)).ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(number)
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            number = number.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    fn size(&mut self) -> Result<usize, PatchError> {
+        let size = self.number()?;
+        if size > MAX_ROM_SIZE as u64 {
+            return Err(PatchError::RomTooLarge(size))
+        }
+        Ok(size as usize)
+    }
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// UPS and BPS end with the CRC32 of the source ROM, target ROM and the patch itself
+struct Footer {
+    source: u32,
+    target: u32
+}
+
+fn check_footer(patch: &[u8], magic_len: usize) -> Result<Footer, PatchError> {
+    if patch.len() < magic_len + 12 {
+        return Err(PatchError::Truncated)
+    }
+    let footer = patch.len() - 12;
+    let expected = read_u32_le(patch, footer + 8);
+    let actual = crc32(&patch[..footer + 8]);
+    if expected != actual {
+        return Err(PatchError::PatchChecksum { expected, actual })
+    }
+    Ok(Footer { source: read_u32_le(patch, footer), target: read_u32_le(patch, footer + 4) })
+}
+
+fn check_source(rom: &[u8], expected_size: usize, footer: &Footer) -> Result<(), PatchError> {
+    if rom.len() != expected_size {
+        return Err(PatchError::SourceSize { expected: expected_size as u64, actual: rom.len() })
+    }
+    let actual = crc32(rom);
+    if actual != footer.source {
+        return Err(PatchError::SourceChecksum { expected: footer.source, actual })
+    }
+    Ok(())
+}
+
+fn check_target(target: Vec<u8>, footer: &Footer) -> Result<Vec<u8>, PatchError> {
+    let actual = crc32(&target);
+    if actual != footer.target {
+        return Err(PatchError::TargetChecksum { expected: footer.target, actual })
+    }
+    Ok(target)
+}
+
+// https://zerosoft.zophar.net/ips.php, records are (24-bit offset, 16-bit length, data) with a length of 0 meaning
+// a run of one byte, an optional 24-bit size after "EOF" truncates the ROM
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader { patch, ptr: 5, end: patch.len() };
+    loop {
+        let offset = reader.u24_be()?;
+        if offset == 0x454F46 { // "EOF"
+            break
+        }
+        let len = reader.u16_be()?;
+        let (len, run) = if len == 0 { (reader.u16_be()?, Some(reader.byte()?)) } else { (len, None) };
+
+        let end = offset + len;
+        if end > MAX_ROM_SIZE {
+            return Err(PatchError::RomTooLarge(end as u64))
+        }
+        if end > target.len() {
+            target.resize(end, 0);
+        }
+        match run {
+            Some(byte) => target[offset..end].fill(byte),
+            None => target[offset..end].copy_from_slice(reader.bytes(len)?)
+        }
+    }
+    if reader.end - reader.ptr >= 3 {
+        target.truncate(reader.u24_be()?);
+    }
+    Ok(target)
+}
+
+// https://www.romhacking.net/documents/392/, hunks of bytes xor'd into the ROM separated by how many bytes to skip
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = check_footer(patch, 4)?;
+    let mut reader = Reader { patch, ptr: 4, end: patch.len() - 12 };
+    let source_size = reader.size()?;
+    let target_size = reader.size()?;
+    check_source(rom, source_size, &footer)?;
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut ptr: usize = 0;
+    while reader.ptr < reader.end {
+        ptr = ptr.saturating_add(reader.size()?);
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                break
+            }
+            if ptr < target.len() {
+                target[ptr] ^= byte;
+            }
+            ptr = ptr.saturating_add(1);
+        }
+        ptr = ptr.saturating_add(1);
+    }
+    check_target(target, &footer)
+}
+
+fn relative_offset(reader: &mut Reader, offset: usize) -> Result<usize, PatchError> {
+    let data = reader.number()?;
+    let distance = usize::try_from(data >> 1).map_err(|_| PatchError::OutOfBounds)?;
+    let offset = if data & 1 != 0 { offset.checked_sub(distance) } else { offset.checked_add(distance) };
+    offset.ok_or(PatchError::OutOfBounds)
+}
+
+// https://www.romhacking.net/documents/746/, the target is built from copies out of the source ROM, the patch or
+// earlier in the target
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = check_footer(patch, 4)?;
+    let mut reader = Reader { patch, ptr: 4, end: patch.len() - 12 };
+    let source_size = reader.size()?;
+    let target_size = reader.size()?;
+    let metadata_size = reader.size()?;
+    reader.bytes(metadata_size)?;
+    check_source(rom, source_size, &footer)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    while reader.ptr < reader.end {
+        let data = reader.size()?;
+        let len = (data >> 2) + 1;
+        if target.len() + len > target_size {
+            return Err(PatchError::OutOfBounds)
+        }
+        match data & 0x3 {
+            0 => { // source read, same position in the source ROM
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..start + len).ok_or(PatchError::OutOfBounds)?);
+            },
+            1 => target.extend_from_slice(reader.bytes(len)?), // target read
+            2 => { // source copy
+                let start = relative_offset(&mut reader, source_offset)?;
+                source_offset = start.checked_add(len).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(rom.get(start..source_offset).ok_or(PatchError::OutOfBounds)?);
+            },
+            _ => { // target copy, can overlap what it's writing to repeat a pattern
+                target_offset = relative_offset(&mut reader, target_offset)?;
+                for _ in 0..len {
+                    let byte = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Truncated)
+    }
+    check_target(target, &footer)
+}
+
+// picks the format from the patch's header
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut number: u64, patch: &mut Vec<u8>) {
+        loop {
+            let bits = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                patch.push(0x80 | bits);
+                return
+            }
+            patch.push(bits);
+            number -= 1;
+        }
+    }
+
+    fn footer(source: &[u8], target: &[u8], patch: &mut Vec<u8>) {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(patch).to_le_bytes());
+    }
+
+    fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        number(source.len() as u64, &mut patch);
+        number(target.len() as u64, &mut patch);
+        let xor = |i: usize| source.get(i).copied().unwrap_or(0) ^ target.get(i).copied().unwrap_or(0);
+
+        let mut last = 0;
+        let mut i = 0;
+        while i < target.len() {
+            if xor(i) == 0 {
+                i += 1;
+                continue
+            }
+            number((i - last) as u64, &mut patch);
+            while i < target.len() && xor(i) != 0 {
+                patch.push(xor(i));
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            last = i;
+        }
+        footer(source, target, &mut patch);
+        patch
+    }
+
+    #[test]
+    fn ips_records_runs_and_truncation() {
+        let rom = vec![0; 16];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x03, 1, 2, 3]); // 3 bytes at 2
+        patch.extend_from_slice(&[0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x04, 0xAA]); // 4 x 0xAA at 14, past the end
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), [0, 0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xAA, 0xAA, 0xAA, 0xAA]);
+
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), [0, 0, 1, 2]);
+        assert_eq!(apply_patch(&rom, &patch[..patch.len() - 10]), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn ups_xors_hunks_into_the_rom() {
+        let source = b"Pocket Monsters Red".to_vec();
+        let target = b"Pokemon Red Version (English)".to_vec();
+        let patch = ups(&source, &target);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        let shrinking = ups(&target, &source);
+        assert_eq!(apply_patch(&target, &shrinking).unwrap(), source);
+
+        let mut other = source.clone();
+        other[0] = b'p';
+        assert_eq!(apply_patch(&other, &patch), Err(PatchError::SourceChecksum { expected: crc32(&source), actual: crc32(&other) }));
+        assert_eq!(apply_patch(&source[1..], &patch), Err(PatchError::SourceSize { expected: source.len() as u64, actual: source.len() - 1 }));
+    }
+
+    #[test]
+    fn bps_copies_from_source_patch_and_target() {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABCxyxyxyxyGHDEF".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        number(source.len() as u64, &mut patch);
+        number(target.len() as u64, &mut patch);
+        number(4, &mut patch);
+        patch.extend_from_slice(b"meta");
+        number((3 - 1) << 2, &mut patch); // source read "ABC"
+        number(((2 - 1) << 2) | 1, &mut patch); // target read "xy"
+        patch.extend_from_slice(b"xy");
+        number(((6 - 1) << 2) | 3, &mut patch); // target copy "xyxyxy" out of the "xy" it's writing
+        number(3 << 1, &mut patch);
+        number(((2 - 1) << 2) | 2, &mut patch); // source copy "GH"
+        number(6 << 1, &mut patch);
+        number(((3 - 1) << 2) | 2, &mut patch); // source copy "DEF", back from after "GH"
+        number((5 << 1) | 1, &mut patch);
+        footer(&source, &target, &mut patch);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        let mut corrupt = patch.clone();
+        corrupt[20] ^= 1;
+        assert!(matches!(apply_patch(&source, &corrupt), Err(PatchError::PatchChecksum { .. })));
+    }
+
+    #[test]
+    fn malformed_patches_are_errors() {
+        assert_eq!(apply_patch(&[0; 4], b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+        assert_eq!(apply_patch(&[0; 4], b"UPS1"), Err(PatchError::Truncated));
+
+        let mut huge = b"UPS1".to_vec();
+        number(u64::MAX / 2, &mut huge);
+        footer(&[], &[], &mut huge);
+        assert_eq!(apply_patch(&[0; 4], &huge), Err(PatchError::RomTooLarge(u64::MAX / 2)));
+
+        let mut out_of_bounds = b"BPS1".to_vec();
+        number(4, &mut out_of_bounds);
+        number(8, &mut out_of_bounds);
+        number(0, &mut out_of_bounds);
+        number((8 - 1) << 2, &mut out_of_bounds); // source read past the end of the source
+        footer(&[0; 4], &[0; 8], &mut out_of_bounds);
+        assert_eq!(apply_patch(&[0; 4], &out_of_bounds), Err(PatchError::OutOfBounds));
+
+        // every prefix of a valid patch fails cleanly
+        let patch = ups(b"source rom", b"target rom!");
+        for len in 0..patch.len() {
+            assert!(apply_patch(b"source rom", &patch[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+use crate::internal::ppu::Display;
+
+pub const BG_SOURCE: u8 = 0; // background and window (BGP)
+pub const OBP0_SOURCE: u8 = 1;
+pub const OBP1_SOURCE: u8 = 2;
+
+// 0xRRGGBB, shades 0-3 from lightest to darkest
+const GREYSCALE: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]; // same as the web frontend
+const DMG_GREEN: [u32; 4] = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+const POCKET_GREY: [u32; 4] = [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F];
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    InvalidSource(u8),
+    InvalidLength(usize) // number of colors given
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::InvalidSource(source) => write!(f, "invalid palette source {} (0 - BG/window, 1 - OBP0, 2 - OBP1)", source),
+            PaletteError::InvalidLength(len) => write!(f, "palette needs exactly 4 colors, got {}", len)
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ColorFormat {
+    Shades, // raw 0-3 shades, 1 byte per pixel
+    Rgba8888, // 4 bytes per pixel
+    Rgb565 // 2 bytes per pixel, little endian
+}
+
+// turns the shades the PPU outputs into colors, BG, OBP0 and OBP1 each get their own palette like the GBC does for DMG games
+pub struct ColorOutput {
+    pub format: ColorFormat,
+    palettes: [[u32; 4]; 3], // indexed by source
+    custom: [[u32; 4]; 3]
+}
+
+impl ColorOutput {
+    pub fn set_format(&mut self, format: u8) { // 0 - shades | 1 - RGBA8888 | 2 - RGB565
+        self.format = match format {
+            1 => ColorFormat::Rgba8888,
+            2 => ColorFormat::Rgb565,
+            _ => ColorFormat::Shades
+        };
+    }
+
+    pub fn set_preset(&mut self, preset: u8) { // 0 - greyscale | 1 - DMG green | 2 - pocket grey | 3 - user-defined
+        self.palettes = match preset {
+            1 => [DMG_GREEN; 3],
+            2 => [POCKET_GREY; 3],
+            3 => self.custom,
+            _ => [GREYSCALE; 3]
+        };
+    }
+
+    // user-defined colors for one source, they become the active palette for it as well
+    pub fn set_palette(&mut self, source: u8, colors: &[u32]) -> Result<(), PaletteError> {
+        if source as usize >= self.palettes.len() {
+            return Err(PaletteError::InvalidSource(source))
+        }
+        let colors: [u32; 4] = colors.try_into().map_err(|_| PaletteError::InvalidLength(colors.len()))?;
+        self.custom[source as usize] = colors;
+        self.palettes[source as usize] = colors;
+        Ok(())
+    }
+
+    fn color(&self, shade: u8, source: u8) -> u32 {
+        self.palettes[source as usize][shade as usize]
+    }
+
+    // always RGBA8888 whatever the selected format is (thumbnails)
+    pub fn to_rgba(&self, lcd: &[u8], sources: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(lcd.len() * 4);
+        for (shade, source) in lcd.iter().zip(sources.iter()) {
+            frame.extend_from_slice(&self.rgba(*shade, *source));
+        }
+        frame
+    }
+
+    fn rgba(&self, shade: u8, source: u8) -> [u8; 4] {
+        let color = self.color(shade, source);
+        [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]
+    }
+
+    pub fn convert(&self, lcd: &Display, sources: &Display) -> Vec<u8> {
+        let mut frame = vec![];
+        self.convert_into(lcd, sources, &mut frame);
+        frame
+    }
+
+    // reuses the allocation of `frame` so converting every frame doesn't allocate
+    pub fn convert_into(&self, lcd: &Display, sources: &Display, frame: &mut Vec<u8>) {
+        frame.clear();
+        match self.format {
+            ColorFormat::Shades => frame.extend_from_slice(lcd),
+            ColorFormat::Rgba8888 => {
+                for (shade, source) in lcd.iter().zip(sources.iter()) {
+                    frame.extend_from_slice(&self.rgba(*shade, *source));
+                }
+            },
+            ColorFormat::Rgb565 => {
+                for (shade, source) in lcd.iter().zip(sources.iter()) {
+                    let color = self.color(*shade, *source);
+                    let (r, g, b) = ((color >> 19) & 0x1F, (color >> 10) & 0x3F, (color >> 3) & 0x1F);
+                    frame.extend_from_slice(&(((r << 11) | (g << 5) | b) as u16).to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+impl Default for ColorOutput {
+    fn default() -> Self {
+        Self {
+            format: ColorFormat::Shades,
+            palettes: [GREYSCALE; 3],
+            custom: [GREYSCALE; 3]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frame() -> (Display, Display) {
+        let mut lcd = [0; 23040];
+        let mut sources = [BG_SOURCE; 23040];
+        lcd[..4].copy_from_slice(&[0, 3, 3, 3]);
+        sources[..4].copy_from_slice(&[BG_SOURCE, BG_SOURCE, OBP0_SOURCE, OBP1_SOURCE]);
+        (lcd, sources)
+    }
+
+    #[test]
+    fn shades_are_passed_through() {
+        let (lcd, sources) = test_frame();
+        let output = ColorOutput::default();
+        assert_eq!(output.convert(&lcd, &sources), lcd.to_vec());
+    }
+
+    #[test]
+    fn rgba_uses_the_palette_of_each_source() {
+        let (lcd, sources) = test_frame();
+        let mut output = ColorOutput::default();
+        output.set_format(1);
+        output.set_preset(1);
+        output.set_palette(OBP1_SOURCE, &[0, 0, 0, 0xFF0000]).unwrap();
+
+        let frame = output.convert(&lcd, &sources);
+        assert_eq!(frame.len(), 23040 * 4);
+        assert_eq!(frame[0..4], [0x9B, 0xBC, 0x0F, 0xFF]);
+        assert_eq!(frame[4..8], [0x0F, 0x38, 0x0F, 0xFF]);
+        assert_eq!(frame[8..12], [0x0F, 0x38, 0x0F, 0xFF]);
+        assert_eq!(frame[12..16], [0xFF, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn rgb565_packs_little_endian() {
+        let (lcd, sources) = test_frame();
+        let mut output = ColorOutput::default();
+        output.set_format(2);
+        output.set_palette(BG_SOURCE, &[0xFFFFFF, 0, 0, 0x00FF00]).unwrap();
+
+        let frame = output.convert(&lcd, &sources);
+        assert_eq!(frame.len(), 23040 * 2);
+        assert_eq!(frame[0..2], [0xFF, 0xFF]);
+        assert_eq!(frame[2..4], 0x07E0u16.to_le_bytes());
+    }
+
+    #[test]
+    fn custom_palette_survives_switching_presets() {
+        let mut output = ColorOutput::default();
+        output.set_palette(OBP0_SOURCE, &[1, 2, 3, 4]).unwrap();
+        output.set_preset(2);
+        assert_eq!(output.color(0, OBP0_SOURCE), 0xC4CFA1);
+
+        output.set_preset(3);
+        assert_eq!(output.color(0, OBP0_SOURCE), 1);
+        assert_eq!(output.color(0, BG_SOURCE), 0xFFFFFF);
+    }
+
+    #[test]
+    fn invalid_palettes_are_rejected() {
+        let mut output = ColorOutput::default();
+        assert_eq!(output.set_palette(3, &[1, 2, 3, 4]), Err(PaletteError::InvalidSource(3)));
+        assert_eq!(output.set_palette(BG_SOURCE, &[1, 2, 3]), Err(PaletteError::InvalidLength(3)));
+        assert_eq!(output.set_palette(BG_SOURCE, &[1, 2, 3, 4, 5]), Err(PaletteError::InvalidLength(5)));
+
+        output.set_preset(3); // nothing was changed
+        assert_eq!(output.color(0, BG_SOURCE), 0xFFFFFF);
+    }
+}
@@ -0,0 +1,129 @@
+// fixed capacity queue backed by a ring buffer, pushing and popping never shifts or allocates memory
+pub struct Fifo<T: Copy + Default, const N: usize> {
+    items: [T; N],
+    head: usize,
+    len: usize
+}
+
+impl<T: Copy + Default, const N: usize> Fifo<T, N> {
+    fn index(&self, i: usize) -> usize {
+        (self.head + i) % N
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.len == N {
+            panic!("pushed to a full FIFO");
+        }
+        let tail = self.index(self.len);
+        self.items[tail] = item;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None
+        }
+        let item = self.items[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    pub fn get(&self, i: usize) -> T { // i counts from the front
+        if i >= self.len {
+            panic!("FIFO index out of bounds");
+        }
+        self.items[self.index(i)]
+    }
+
+    pub fn set(&mut self, i: usize, item: T) {
+        if i >= self.len {
+            panic!("FIFO index out of bounds");
+        }
+        let index = self.index(i);
+        self.items[index] = item;
+    }
+
+    pub fn swap(&mut self, i: usize, j: usize) {
+        let item = self.get(i);
+        self.set(i, self.get(j));
+        self.set(j, item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for Fifo<T, N> {
+    fn default() -> Self {
+        Self {
+            items: [T::default(); N],
+            head: 0,
+            len: 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let mut fifo: Fifo<u8, 8> = Fifo::default();
+        for round in 0..3 {
+            for i in 0..8 {
+                fifo.push(round * 8 + i);
+            }
+            assert_eq!(fifo.len(), 8);
+            assert_eq!(fifo.get(7), round * 8 + 7);
+
+            for i in 0..5 {
+                assert_eq!(fifo.pop(), Some(round * 8 + i));
+            }
+            for i in 5..8 {
+                assert_eq!(fifo.pop(), Some(round * 8 + i));
+            }
+            assert_eq!(fifo.pop(), None);
+        }
+    }
+
+    #[test]
+    fn set_and_swap_index_from_front() {
+        let mut fifo: Fifo<u8, 4> = Fifo::default();
+        fifo.push(0);
+        fifo.push(0);
+        fifo.pop();
+        fifo.pop(); // head is now in the middle of the buffer
+        for i in 1..=4 {
+            fifo.push(i);
+        }
+
+        fifo.set(3, 9);
+        fifo.swap(0, 1);
+        assert_eq!([fifo.get(0), fifo.get(1), fifo.get(2), fifo.get(3)], [2, 1, 3, 9]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_to_full_fifo_panics() {
+        let mut fifo: Fifo<u8, 2> = Fifo::default();
+        for i in 0..3 {
+            fifo.push(i);
+        }
+    }
+}
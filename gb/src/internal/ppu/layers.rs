@@ -0,0 +1,143 @@
+use crate::internal::ppu::Display;
+
+pub const TRANSPARENT: u8 = 0xFF; // pixel not drawn by the layer
+
+pub const BACKGROUND_LAYER: u8 = 0;
+pub const WINDOW_LAYER: u8 = 1;
+pub const SPRITE_LAYER: u8 = 2;
+pub const SPRITE_OWNER_LAYER: u8 = 3; // OAM index of the sprite that won each pixel
+
+// debug switches that sit on top of whatever the game has set in LCDC
+pub struct Layers {
+    pub hide_background: bool,
+    pub hide_window: bool,
+    pub hide_sprites: bool,
+    pub separate: bool, // when set every pixel is also drawn into the buffer of the layer it came from
+
+    pub background: Display,
+    pub window: Display,
+    pub sprites: Display,
+    pub sprite_owner: Display
+}
+
+impl Layers {
+    pub fn set_visible(&mut self, layer: u8, visible: bool) {
+        match layer {
+            BACKGROUND_LAYER => self.hide_background = !visible,
+            WINDOW_LAYER => self.hide_window = !visible,
+            SPRITE_LAYER => self.hide_sprites = !visible,
+            _ => ()
+        }
+    }
+
+    pub fn set_separate(&mut self, separate: bool) {
+        self.separate = separate;
+        self.background = [TRANSPARENT; 23040];
+        self.window = [TRANSPARENT; 23040];
+        self.sprites = [TRANSPARENT; 23040];
+        self.sprite_owner = [TRANSPARENT; 23040];
+    }
+
+    pub fn get(&self, layer: u8) -> Display {
+        match layer {
+            BACKGROUND_LAYER => self.background,
+            WINDOW_LAYER => self.window,
+            SPRITE_LAYER => self.sprites,
+            SPRITE_OWNER_LAYER => self.sprite_owner,
+            _ => [TRANSPARENT; 23040]
+        }
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            hide_background: false,
+            hide_window: false,
+            hide_sprites: false,
+            separate: false,
+            background: [TRANSPARENT; 23040],
+            window: [TRANSPARENT; 23040],
+            sprites: [TRANSPARENT; 23040],
+            sprite_owner: [TRANSPARENT; 23040]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::ppu::{PPU, DOTS_PER_FRAME};
+
+    // background of solid color 3 except for a transparent tile at x = 8..16, with sprite tile 1 in solid color 1
+    fn setup_ppu() -> PPU {
+        let mut ppu = PPU {
+            bgp: 0b11100100,
+            obp0: 0b11100100,
+            ..PPU::default()
+        };
+        ppu.write_registers(0xFF40, 0b10000011);
+        ppu.vram[0x1000..0x1010].fill(0xFF); // tile 0 in the signed addressing block
+        ppu.vram[0x1801] = 1;
+        for row in 0..8 {
+            ppu.vram[16 + (row * 2)] = 0xFF;
+        }
+        ppu.layers.set_separate(true);
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, x_pos: u8, flags: u8) {
+        ppu.oam[index * 4..(index * 4) + 4].copy_from_slice(&[16, x_pos, 1, flags]);
+    }
+
+    fn run_frames(ppu: &mut PPU, frames: usize) {
+        for _ in 0..(frames * DOTS_PER_FRAME / 4) {
+            ppu.update();
+        }
+    }
+
+    #[test]
+    fn sprite_behind_opaque_background_owns_nothing() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 5, 8, 1 << 7); // x = 0..8, over background color 3
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[0..8], [3; 8]);
+        assert_eq!(ppu.layers.sprites[0..8], [1; 8]); // still drawn into its own layer
+        assert_eq!(ppu.layers.sprite_owner[0..8], [TRANSPARENT; 8]);
+        assert_eq!(ppu.layers.background[0..8], [3; 8]);
+    }
+
+    #[test]
+    fn sprite_behind_transparent_background_owns_its_pixels() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 5, 12, 1 << 7); // x = 4..12, half over the transparent tile
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[4..12], [3, 3, 3, 3, 1, 1, 1, 1]);
+        assert_eq!(ppu.layers.sprite_owner[4..12], [TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn sprite_in_front_owns_its_pixels() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 7, 8, 0x00);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[0..8], [1; 8]);
+        assert_eq!(ppu.layers.sprite_owner[0..8], [7; 8]);
+        assert_eq!(ppu.layers.sprite_owner[8..16], [TRANSPARENT; 8]);
+    }
+
+    #[test]
+    fn hidden_background_loses_to_sprites_behind_it() {
+        let mut ppu = setup_ppu();
+        ppu.layers.set_visible(BACKGROUND_LAYER, false);
+        set_sprite(&mut ppu, 5, 8, 1 << 7);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[0..8], [1; 8]);
+        assert_eq!(ppu.layers.background[0..8], [TRANSPARENT; 8]);
+        assert_eq!(ppu.layers.sprite_owner[0..8], [5; 8]);
+    }
+}
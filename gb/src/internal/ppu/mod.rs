@@ -0,0 +1,1290 @@
+pub mod viewer;
+pub mod layers;
+pub mod fifo;
+pub mod scanline;
+pub mod color;
+pub mod state;
+
+use crate::internal::ppu::layers::{Layers, TRANSPARENT};
+use crate::internal::ppu::fifo::Fifo;
+use crate::internal::ppu::scanline::Renderer;
+use crate::internal::ppu::color::{ColorOutput, BG_SOURCE};
+
+const LCD_ENABLED: u8 = 7;
+const WINDOW_TILE_MAP: u8 = 6;
+const WINDOW_ENABLED: u8 = 5;
+const TILE_ADDRESSING: u8 = 4;
+const BG_TILE_MAP: u8 = 3;
+const SPRITE_SIZE: u8 = 2;
+const SPRITES_ENABLED: u8 = 1;
+const BG_OR_WINDOW_ENABLED: u8 = 0;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Mode {
+    OAMSCAN, DRAW, HBLANK, VBLANK
+}
+
+pub type Display = [u8; 23040];
+
+const DOTS_PER_FRAME: usize = 456 * 154;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum LcdState {
+    Off, // LY and mode stay at 0, frames keep being paced so the blank screen still gets presented
+    FirstFrame, // line 0 skips OAM scan and nothing is output until the next frame
+    On
+}
+
+pub struct PPU {
+    pub lcd: Display,
+    pub lcd_source: Display, // which palette each pixel on the LCD went through
+    pub colors: ColorOutput,
+    pub oam: [u8; 0xA0],
+    pub vram: [u8; 0x2000],
+    pub rendered_frame: bool,
+    pub debug_panel: [usize; 144 * 3],
+    pub layers: Layers,
+    renderer: Renderer,
+    control: u8,
+    stat: u8,
+    ly: u8,
+    lyc: u8,
+    scy: u8,
+    scx: u8,
+    wy: u8,
+    wx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    scanline_timeline: usize,
+    vblank_timeline: usize,
+    window_in_frame: bool,
+    window_line_counter: usize,
+    rendered_window_on_scanline: bool,
+    tick_state: TickState,
+    lcd_state: LcdState,
+    off_timeline: usize,
+    stat_line: bool, // all enabled STAT sources OR'd together, an interrupt is only requested when it goes high
+    requested_interrupts: u8, // IF bits raised since the bus last collected them
+
+    sprite_fifo: Fifo<ObjectPixel, 8>,
+    background_fifo: Fifo<u8, 16>,
+    sprite_buffer: Fifo<Object, 10>, // objects found during OAM scan
+}
+
+struct TickState {
+    is_fetching_window: bool,
+    fetcher_x: usize,
+    scanline_x: usize,
+    tile_number: u8,
+    tile_data_low: u8,
+    tile_data_high: u8,
+    current_sprite: Option<Object>,
+    new_scanline: bool,
+
+    oam_ptr: usize,
+    bg_fetcher_step: u8, // dots into the current tile fetch, 6 when waiting to push
+    sprite_fetcher_step: u8, // dots left until the current sprite has been fetched
+    pixels_to_discard: u8,
+    draw_dots_left: usize // scanline renderer only
+}
+
+#[derive(Clone, Copy, Default)]
+struct Object {
+    y_pos: u8,
+    x_pos: u8,
+    tile_number: u8,
+    sprite_flags: u8,
+    oam_index: u8
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjectPixel {
+    color_id: u8,
+    flags: u8,
+    x_pos: u8,
+    oam_index: u8
+}
+
+impl PPU {
+    pub fn read_registers(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.control,
+            0xFF41 => self.stat,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF46 => 0xFF,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write_registers(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = (self.control >> LCD_ENABLED) & 0x1 == 1;
+                self.control = val;
+
+                let is_enabled = (self.control >> LCD_ENABLED) & 0x1 == 1;
+                if was_enabled && !is_enabled {
+                    self.power_off();
+                } else if !was_enabled && is_enabled {
+                    self.power_on();
+                }
+            },
+            0xFF41 => {
+                self.stat = (val & 0x78) | (self.stat & 0x07); // bottom 3 bits are read only
+                self.update_stat_line();
+            },
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            0xFF44 => (), // Read only.
+            0xFF45 => {
+                self.lyc = val;
+                self.update_coincidence();
+                self.update_stat_line();
+            },
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val, 
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+
+            _ => panic!("recieved invalid address")
+        };
+    }
+
+    fn update_coincidence(&mut self) {
+        if self.ly == self.lyc {
+            self.stat |= 1 << 2;
+        } else {
+            self.stat &= !(1 << 2);
+        }
+    }
+
+    fn power_off(&mut self) {
+        self.lcd_state = LcdState::Off;
+        self.off_timeline = 0;
+
+        self.stat &= 0b11111100; // reset stat mode to 0
+        self.ly = 0; // reset ly to 0
+        self.update_coincidence();
+        self.stat_line = false;
+        self.lcd = [0x00; 23040]; // white out background
+        self.lcd_source = [BG_SOURCE; 23040];
+
+        self.scanline_timeline = 0;
+        self.vblank_timeline = 0;
+        self.window_in_frame = false;
+        self.window_line_counter = 0;
+        self.rendered_window_on_scanline = false;
+        self.tick_state = TickState::default();
+        self.background_fifo.clear();
+        self.sprite_fifo.clear();
+        self.sprite_buffer.clear();
+    }
+
+    fn power_on(&mut self) {
+        // comes back in mode 0 at the very start of line 0, the OAM scan is skipped (see HBLANK)
+        self.lcd_state = LcdState::FirstFrame;
+        self.scanline_timeline = 0;
+        self.update_coincidence();
+        self.update_stat_line();
+    }
+
+    fn update_stat_line(&mut self) {
+        if self.lcd_state == LcdState::Off {
+            return
+        }
+
+        let mode = self.stat & 0x3;
+        let line = ((self.stat >> 6) & 0x1 == 1 && (self.stat >> 2) & 0x1 == 1) // LY == LYC
+            || ((self.stat >> 5) & 0x1 == 1 && mode == 2) // OAM
+            || ((self.stat >> 4) & 0x1 == 1 && mode == 1) // VBLANK
+            || ((self.stat >> 3) & 0x1 == 1 && mode == 0); // HBLANK
+
+        if line && !self.stat_line { // sources that are already high block the others (STAT blocking)
+            self.requested_interrupts |= 0b00000010;
+        }
+        self.stat_line = line;
+    }
+
+    pub fn take_requested_interrupts(&mut self) -> u8 {
+        let requests = self.requested_interrupts;
+        self.requested_interrupts = 0;
+        requests
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        match self.stat & 0x3 {
+            0 => Mode::HBLANK,
+            1 => Mode::VBLANK,
+            2 => Mode::OAMSCAN,
+            3 => Mode::DRAW,
+            _ => panic!("Unexpected branch.")
+        }
+    }
+
+    pub fn get_position(&self) -> (u8, u16) { // (LY, dot within the scanline)
+        (self.ly, self.scanline_timeline as u16)
+    }
+
+    fn update_mode(&mut self, mode: Mode) {
+        self.stat &= 0b11111100;
+        match mode {
+            Mode::OAMSCAN => {
+                self.debug_panel[((self.ly as usize) * 3) + 2] = self.scanline_timeline; // hblank had just ended
+                self.stat |= 0b00000010
+            },
+            Mode::DRAW => {
+                self.debug_panel[(self.ly as usize) * 3] = self.scanline_timeline; // oam had just ended
+                self.stat |= 0b00000011
+            },
+            Mode::HBLANK => {
+                self.debug_panel[((self.ly as usize) * 3) + 1] = self.scanline_timeline; // draw has just ended
+                self.stat |= 0b00000000
+            },
+            Mode::VBLANK => {
+                self.requested_interrupts |= 0b00000001;
+                self.stat |= 0b00000001
+            },
+        };
+    }
+
+    // DMG object priority: the lower X coordinate wins and ties are broken by the lower OAM index. Sprites are
+    // fetched in that order and a fetched pixel never overwrites an opaque one, so sorting the buffer is enough.
+    fn resolve_sprite_priority(&mut self) {
+        // insertion sort is stable so sprites sharing an X keep their OAM order
+        for i in 1..self.sprite_buffer.len() {
+            let mut j = i;
+            while j > 0 && self.sprite_buffer.get(j - 1).x_pos > self.sprite_buffer.get(j).x_pos {
+                self.sprite_buffer.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+
+    fn detect_sprite(&mut self) -> Option<Object> {
+        // buffer is sorted by priority so only the front can be the next sprite on the scanline
+        if !self.sprite_buffer.is_empty() && self.sprite_buffer.get(0).x_pos as usize <= self.tick_state.scanline_x + 8 {
+            return self.sprite_buffer.pop();
+        }
+
+        return None
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        if self.get_mode() != Mode::DRAW {
+            return self.vram[addr as usize];
+        }
+        0xFF
+    }
+
+    pub fn write_vram(&mut self, addr: u16, val: u8) {
+        if self.get_mode() != Mode::DRAW {
+            self.vram[addr as usize] = val;
+        }
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        if self.get_mode() != Mode::DRAW && self.get_mode() != Mode::OAMSCAN {
+            return self.oam[addr as usize];
+        }
+        return 0xFF;
+    }
+
+    pub fn write_oam(&mut self, addr: u16, val: u8) {
+        if self.get_mode() != Mode::DRAW && self.get_mode() != Mode::OAMSCAN {
+            self.oam[addr as usize] = val;
+        }
+    }
+
+    // reads the sprite's row once its fetch is over and mixes it into the sprite FIFO
+    fn sprite_pixel_fetcher(&mut self, sprite: Object) {
+        let sprite_height = (if self.control >> SPRITE_SIZE & 0x1 == 1 { 16 } else { 8 }) as u16;
+
+        let vertical_flip = (sprite.sprite_flags >> 6) & 0x1 == 1;
+
+        // shoutout to nemo for helping me with this math lol
+        let mut vertical_offset = ((self.ly as u16).wrapping_sub((sprite.y_pos as u16).wrapping_sub(16)) % sprite_height).wrapping_mul(2) as u16;
+        if vertical_flip {
+            vertical_offset = ((sprite_height - 1) * 2) - vertical_offset;
+        }
+
+        let tile: u16 = sprite.tile_number as u16 * 16;
+        let tile_data_low = self.vram[(tile + vertical_offset) as usize];
+        let tile_data_high = self.vram[(tile + vertical_offset + 1) as usize];
+
+        let horizontal_flip = sprite.sprite_flags >> 5 & 0x1 == 1;
+        let base = if sprite.x_pos < 8 { 8 - sprite.x_pos } else { 0 };
+
+        for i in (base as usize)..8 {
+            let pos = if horizontal_flip { i } else { 7 - i };
+            let fifo_index = i - base as usize; // columns left of the screen are never pushed
+            let pixel = ObjectPixel {
+                color_id: (((tile_data_high >> pos) & 0x1) << 1) | ((tile_data_low >> pos) & 0x1),
+                flags: sprite.sprite_flags,
+                x_pos: sprite.x_pos,
+                oam_index: sprite.oam_index
+            };
+
+            // mix overlapping pixels, the sprite already in the FIFO has priority unless it is transparent
+            if fifo_index < self.sprite_fifo.len() {
+                if self.sprite_fifo.get(fifo_index).color_id == 0 && pixel.color_id != 0 {
+                    self.sprite_fifo.set(fifo_index, pixel);
+                }
+            } else {
+                self.sprite_fifo.push(pixel);
+            }
+        }
+    }
+
+    fn background_tile_data_addr(&self) -> u16 {
+        let offset = if self.tick_state.is_fetching_window { (2 * (self.window_line_counter % 8)) as u16 } else { 2 * ((self.ly as u16 + self.scy as u16) % 8) };
+
+        if (self.control >> TILE_ADDRESSING) & 0x1 == 1 {
+            0x8000 + (self.tick_state.tile_number as u16 * 16) + offset
+        } else {
+            (0x9000 as u16).wrapping_add_signed((self.tick_state.tile_number as i8 as i16) * 16) + offset
+        }
+    }
+
+    // 1 dot. Each fetch step takes 2 dots (tile number, data low, data high), after which the tile is pushed as soon as the FIFO is empty
+    fn background_pixel_fetcher(&mut self) {
+        match self.tick_state.bg_fetcher_step {
+            1 => {
+                let mut tile_map: u16 = 0x9800;
+                let tile_x;
+                let tile_y;
+
+                if self.tick_state.is_fetching_window {
+                    self.rendered_window_on_scanline = true;
+                    if (self.control >> WINDOW_TILE_MAP) & 0x1 == 1 {
+                        tile_map = 0x9C00;
+                    }
+                    tile_x = self.tick_state.fetcher_x as u16 & 0x1F;
+                    tile_y = (32 * (self.window_line_counter / 8)) as u16;
+                } else {
+                    if (self.control >> BG_TILE_MAP) & 0x1 == 1 {
+                        tile_map = 0x9C00;
+                    }
+                    tile_x = (self.tick_state.fetcher_x as u16 + ((self.scx as u16) / 8)) & 0x1F;
+                    tile_y = 32 * ((((self.ly as u16) + (self.scy as u16)) & 0xFF) / 8);
+                }
+                self.tick_state.tile_number = self.vram[((tile_map + ((tile_x + tile_y) & 0x3FF)) - 0x8000) as usize];
+            },
+            3 => self.tick_state.tile_data_low = self.vram[(self.background_tile_data_addr() - 0x8000) as usize],
+            5 => self.tick_state.tile_data_high = self.vram[(self.background_tile_data_addr() + 1 - 0x8000) as usize],
+            _ => ()
+        }
+
+        if self.tick_state.bg_fetcher_step < 6 {
+            self.tick_state.bg_fetcher_step += 1;
+        }
+
+        if self.tick_state.bg_fetcher_step == 6 && self.background_fifo.is_empty() {
+            self.tick_state.bg_fetcher_step = 0;
+
+            if self.tick_state.new_scanline { // the first fetch of every scanline is thrown away
+                self.tick_state.new_scanline = false;
+                return
+            }
+
+            for i in 0..8 {
+                if (self.control >> BG_OR_WINDOW_ENABLED) & 0x1 == 0 { // clear background with white pixels, sprites unaffected.
+                    self.background_fifo.push(0);
+                } else {
+                    self.background_fifo.push((((self.tick_state.tile_data_high >> (7 - i)) & 0x1) << 1) | ((self.tick_state.tile_data_low >> (7 - i)) & 0x1));
+                }
+            }
+            self.tick_state.fetcher_x += 1;
+        }
+    }
+
+    // 1 dot. Shifts out a pixel unless it has to wait on the FIFO, SCX fine scrolling, the window or a sprite
+    fn pixel_shifter(&mut self) {
+        if self.tick_state.current_sprite.is_some() { // sprite fetches stall the shifter
+            self.tick_state.sprite_fetcher_step -= 1;
+            if self.tick_state.sprite_fetcher_step == 0 {
+                let sprite = self.tick_state.current_sprite.take().unwrap();
+                self.sprite_pixel_fetcher(sprite);
+            }
+            return
+        }
+
+        if self.background_fifo.is_empty() {
+            return
+        }
+
+        if self.tick_state.pixels_to_discard > 0 { // SCX mod 8 pixels are dropped at the start of the scanline, 1 per dot
+            self.background_fifo.pop();
+            self.tick_state.pixels_to_discard -= 1;
+            return
+        }
+
+        /* Encountered window for the first time on a scanline, the fetcher restarts which costs 6 dots */
+        if !self.tick_state.is_fetching_window && self.window_in_frame && ((self.control >> WINDOW_ENABLED) & 0x1 == 1) && self.wx <= self.tick_state.scanline_x as u8 + 7 {
+            self.tick_state.is_fetching_window = true;
+            self.tick_state.bg_fetcher_step = 0;
+            self.tick_state.fetcher_x = 0;
+            self.background_fifo.clear();
+            return
+        }
+
+        if (self.control >> SPRITES_ENABLED) & 0x1 == 1 {
+            if let Some(sprite) = self.detect_sprite() {
+                // the sprite fetch takes 6 dots but first waits for the background fetcher to get through its current tile
+                let bg_fetch_remaining = 5u8.saturating_sub(self.tick_state.bg_fetcher_step);
+                self.tick_state.sprite_fetcher_step = 6 + bg_fetch_remaining - 1; // this dot is part of the penalty
+                self.tick_state.current_sprite = Some(sprite);
+                return
+            }
+        }
+
+        let bg_color_id = self.background_fifo.pop().unwrap();
+        let sprite = self.sprite_fifo.pop();
+
+        self.push_pixel(bg_color_id, sprite);
+        self.tick_state.scanline_x += 1;
+
+        if self.tick_state.scanline_x > 159 {
+            self.update_mode(Mode::HBLANK);
+        }
+    }
+
+    fn get_object_color(&self, pallete: u8, color_id: u8) -> u8 {
+        if pallete == 1 {
+            return (self.obp1 >> (2 * color_id)) & 0x3
+        } else if pallete == 0 {
+            return (self.obp0 >> (2 * color_id)) & 0x3
+        }
+        panic!("invalid pallete number!");
+    }
+
+    // mixes the background/window pixel with the sprite pixel (if any) and draws it to the LCD
+    fn push_pixel(&mut self, bg_color_id: u8, sprite: Option<ObjectPixel>) {
+        if self.lcd_state == LcdState::FirstFrame { // the frame right after enabling the LCD is never shown
+            return
+        }
+
+        let offset = (self.ly as usize * 160) + self.tick_state.scanline_x;
+        let is_window = self.tick_state.is_fetching_window;
+
+        let bg_hidden = if is_window { self.layers.hide_window } else { self.layers.hide_background };
+        let bg_color_id = if bg_hidden { 0 } else { bg_color_id };
+        let bg_color_value = (self.bgp >> (bg_color_id * 2)) & 0x3;
+
+        // sprite is transparent so background is visible
+        let sprite = sprite.filter(|sprite| sprite.color_id != 0x00 && !self.layers.hide_sprites);
+
+        if self.layers.separate {
+            self.layers.background[offset] = if is_window || bg_hidden { TRANSPARENT } else { bg_color_value };
+            self.layers.window[offset] = if !is_window || bg_hidden { TRANSPARENT } else { bg_color_value };
+            self.layers.sprites[offset] = match &sprite {
+                Some(sprite) => self.get_object_color((sprite.flags >> 4) & 0x1, sprite.color_id),
+                None => TRANSPARENT
+            };
+        }
+
+        // background has priority and isn't transparent
+        let sprite = sprite.filter(|sprite| (sprite.flags >> 7) & 0x1 == 0 || bg_color_id == 0);
+
+        if self.layers.separate { // only the sprite that actually ends up on the LCD owns the pixel
+            self.layers.sprite_owner[offset] = match &sprite {
+                Some(sprite) => sprite.oam_index,
+                None => TRANSPARENT
+            };
+        }
+
+        (self.lcd[offset], self.lcd_source[offset]) = match sprite {
+            Some(sprite) => (self.get_object_color((sprite.flags >> 4) & 0x1, sprite.color_id), 1 + ((sprite.flags >> 4) & 0x1)), // OBP0 or OBP1
+            None => (bg_color_value, BG_SOURCE)
+        };
+    }
+
+    fn start_drawing(&mut self) {
+        self.update_mode(Mode::DRAW);
+        self.tick_state.pixels_to_discard = self.scx % 8;
+        if self.renderer == Renderer::Scanline {
+            self.tick_state.draw_dots_left = self.render_scanline();
+        }
+    }
+
+    // adds the OAM entry at oam_ptr to the sprite buffer if it is on this scanline
+    fn scan_oam_entry(&mut self) {
+        if self.sprite_buffer.len() >= 10 {
+            return
+        }
+
+        let base_ptr = 4 * self.tick_state.oam_ptr;
+
+        let y_pos = self.oam[base_ptr];
+        let x_pos = self.oam[base_ptr + 1];
+        let mut tile_number = self.oam[base_ptr + 2];
+        let sprite_flags = self.oam[base_ptr + 3];
+
+        let mut sprite_height: u8 = 8;
+        if (self.control >> SPRITE_SIZE) & 0x1 == 1 {
+            tile_number &= 0b11111110; // bit 0 of tile index for 8x16 objects should be ignored
+            sprite_height = 16;
+        }
+
+        // only Y is checked, off-screen X positions still count towards the 10 sprite limit
+        if (self.ly as u16) + 16 >= y_pos as u16 && (self.ly as u16) + 16 < (y_pos as u16) + (sprite_height as u16) {
+            self.sprite_buffer.push(Object {
+                y_pos,
+                x_pos,
+                tile_number,
+                sprite_flags,
+                oam_index: self.tick_state.oam_ptr as u8
+            })
+        }
+    }
+
+    fn tick(&mut self) { // 1 dot
+        self.scanline_timeline += 1;
+
+        if self.wy == self.ly {
+            self.window_in_frame = true;
+        }
+
+        match self.get_mode() {
+            Mode::OAMSCAN => {
+                if self.scanline_timeline % 2 == 1 { // each OAM entry takes 2 dots
+                    return
+                }
+
+                self.scan_oam_entry();
+                if self.tick_state.oam_ptr < 39 {
+                    self.tick_state.oam_ptr += 1;
+                } else {
+                    self.resolve_sprite_priority();
+                    self.tick_state.oam_ptr = 0;
+                    self.start_drawing();
+                }
+            },
+            Mode::DRAW if self.renderer == Renderer::Scanline => {
+                self.tick_state.draw_dots_left -= 1;
+                if self.tick_state.draw_dots_left == 0 {
+                    self.update_mode(Mode::HBLANK);
+                }
+            },
+            Mode::DRAW => {
+                // the shifter works off what the fetcher had pushed before this dot
+                self.pixel_shifter();
+                if self.get_mode() == Mode::DRAW {
+                    self.background_pixel_fetcher();
+                }
+            },
+            Mode::HBLANK => {
+                self.tick_state = TickState::default();
+                self.background_fifo.clear();
+                self.sprite_fifo.clear();
+                self.sprite_buffer.clear();
+
+                // first line after enabling the LCD stays in mode 0 instead of scanning OAM
+                if self.lcd_state == LcdState::FirstFrame && self.ly == 0 && self.scanline_timeline == 80 {
+                    self.start_drawing();
+                }
+
+                if self.scanline_timeline == 456 { // 456 dots per scanline
+                    if self.rendered_window_on_scanline {
+                        self.window_line_counter += 1;
+                        self.rendered_window_on_scanline = false;
+                    }
+
+                    self.ly += 1;
+                    self.update_coincidence();
+                    if self.ly > 143 {
+                        self.update_mode(Mode::VBLANK);
+                    } else {
+                        self.update_mode(Mode::OAMSCAN);
+                    }
+                }
+            },
+            Mode::VBLANK => {
+                self.window_line_counter = 0;
+                self.vblank_timeline += 1;
+                if self.vblank_timeline == 4560 { // 4560 dots per vblank
+                    self.rendered_frame = true;
+                    self.lcd_state = LcdState::On;
+                    self.vblank_timeline = 0;
+                    self.window_in_frame = false;
+                    self.update_mode(Mode::OAMSCAN);
+                } else if self.vblank_timeline == (9 * 456) + 4 { // LY reads 0 for the rest of line 153
+                    self.ly = 0;
+                    self.update_coincidence();
+                } else if self.vblank_timeline % 456 == 0 {
+                    self.ly += 1;
+                    self.update_coincidence();
+                }
+            }
+        }
+
+        if self.scanline_timeline == 456 {
+            self.scanline_timeline = 0;
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.lcd_state == LcdState::Off {
+            self.off_timeline += 4;
+            if self.off_timeline >= DOTS_PER_FRAME { // present the blank screen at the usual frame rate
+                self.off_timeline -= DOTS_PER_FRAME;
+                self.rendered_frame = true;
+            }
+            return
+        }
+
+        for _ in 0..4 {
+            self.tick();
+            self.update_stat_line();
+        }
+    }
+
+    // dots until the next mode or LY change (or the end of a frame with the LCD off), mode 3 uses its minimum length
+    fn dots_until_event(&self) -> usize {
+        if self.lcd_state == LcdState::Off {
+            return DOTS_PER_FRAME - self.off_timeline
+        }
+
+        match self.get_mode() {
+            Mode::OAMSCAN => 80 - self.scanline_timeline,
+            Mode::DRAW if self.renderer == Renderer::Scanline => self.tick_state.draw_dots_left,
+            Mode::DRAW => (80 + 172usize).saturating_sub(self.scanline_timeline).max(1),
+            Mode::HBLANK => {
+                if self.lcd_state == LcdState::FirstFrame && self.ly == 0 && self.scanline_timeline < 80 {
+                    80 - self.scanline_timeline
+                } else {
+                    456 - self.scanline_timeline
+                }
+            },
+            Mode::VBLANK => {
+                let line_153 = (9 * 456) + 4;
+                if self.vblank_timeline < line_153 && self.vblank_timeline >= 9 * 456 {
+                    line_153 - self.vblank_timeline
+                } else {
+                    456 - (self.vblank_timeline % 456)
+                }
+            }
+        }
+    }
+
+    pub fn cycles_until_event(&self) -> usize { // M-cycles
+        self.dots_until_event().div_ceil(4)
+    }
+
+    // same as calling update() `cycles` times, stretches where only the timelines move are skipped in one go
+    pub fn run(&mut self, mut cycles: usize) {
+        while cycles > 0 {
+            // OAM, LCDC and LY can't change without a catch up first, so the OAM scan can be done up front
+            let idle = match self.get_mode() { // the LCD being off counts as HBLANK
+                Mode::HBLANK | Mode::VBLANK | Mode::OAMSCAN => ((self.dots_until_event() - 1) / 4).min(cycles),
+                Mode::DRAW if self.renderer == Renderer::Scanline => ((self.dots_until_event() - 1) / 4).min(cycles),
+                Mode::DRAW => 0
+            };
+
+            if idle == 0 {
+                self.update();
+                cycles -= 1;
+                continue
+            }
+
+            cycles -= idle;
+            if self.lcd_state == LcdState::Off {
+                self.off_timeline += idle * 4;
+                continue
+            }
+
+            if self.wy == self.ly {
+                self.window_in_frame = true;
+            }
+            self.scanline_timeline += idle * 4;
+            if self.get_mode() == Mode::OAMSCAN { // entries are scanned every other dot, the last one starts mode 3 in update()
+                while self.tick_state.oam_ptr < self.scanline_timeline / 2 {
+                    self.scan_oam_entry();
+                    self.tick_state.oam_ptr += 1;
+                }
+            } else if self.get_mode() == Mode::DRAW {
+                self.tick_state.draw_dots_left -= idle * 4;
+            } else if self.get_mode() == Mode::VBLANK {
+                self.window_line_counter = 0;
+                self.vblank_timeline += idle * 4;
+            }
+        }
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self {
+            lcd: [0; 23040],
+            lcd_source: [BG_SOURCE; 23040],
+            colors: ColorOutput::default(),
+            debug_panel: [0; 144 * 3],
+            layers: Layers::default(),
+            renderer: Renderer::Fifo,
+            vram: [0x0; 0x2000],
+            oam: [0x0; 0xA0],
+            ly: 0,
+            lyc: 0,
+            control: 0x0,
+            stat: 0x0,
+            scy: 0,
+            scx: 0,
+            wy: 0,
+            wx: 0,
+            bgp: 0x0,
+            obp0: 0x0,
+            obp1: 0x0,
+            tick_state: TickState::default(),
+            scanline_timeline: 0,
+            vblank_timeline: 0,
+            sprite_buffer: Fifo::default(),
+            background_fifo: Fifo::default(),
+            sprite_fifo: Fifo::default(),
+            window_in_frame: false,
+            window_line_counter: 0,
+            rendered_window_on_scanline: false,
+            rendered_frame: false,
+            lcd_state: LcdState::Off,
+            off_timeline: 0,
+            stat_line: false,
+            requested_interrupts: 0,
+        }
+    }
+}
+
+impl Default for TickState {
+    fn default() -> Self {
+        Self {
+            oam_ptr: 0,
+            fetcher_x: 0,
+            scanline_x: 0,
+            bg_fetcher_step: 0,
+            sprite_fetcher_step: 0,
+            tile_data_high: 0,
+            tile_data_low: 0,
+            tile_number: 0,
+            new_scanline: true,
+            current_sprite: None,
+            is_fetching_window: false,
+            pixels_to_discard: 0,
+            draw_dots_left: 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LCD, sprites and background on with identity palettes except OBP1 which is inverted
+    fn setup_ppu() -> PPU {
+        let mut ppu = PPU {
+            bgp: 0b11100100,
+            obp0: 0b11100100,
+            obp1: 0b00011011,
+            ..PPU::default()
+        };
+        ppu.write_registers(0xFF40, 0b10000011);
+
+        for row in 0..8 {
+            ppu.vram[16 + (row * 2)] = 0xFF; // tile 1: solid color 1
+            ppu.vram[32 + (row * 2)] = 0xFF; // tile 2: solid color 3
+            ppu.vram[32 + (row * 2) + 1] = 0xFF;
+            ppu.vram[48 + (row * 2)] = 0x01; // tile 3: only the rightmost column, color 1
+        }
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, y_pos: u8, x_pos: u8, tile_number: u8, flags: u8) {
+        ppu.oam[index * 4..(index * 4) + 4].copy_from_slice(&[y_pos, x_pos, tile_number, flags]);
+    }
+
+    fn run_frames(ppu: &mut PPU, frames: usize) {
+        for _ in 0..(frames * DOTS_PER_FRAME / 4) {
+            ppu.update();
+        }
+    }
+
+    #[test]
+    fn same_x_lower_oam_index_wins() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 0, 16, 20, 1, 0x00);
+        set_sprite(&mut ppu, 1, 16, 20, 2, 0x00);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[12..20], [1; 8]);
+    }
+
+    #[test]
+    fn lcd_source_follows_the_winning_palette() {
+        let mut ppu = setup_ppu();
+        fill_background(&mut ppu);
+        set_sprite(&mut ppu, 0, 16, 8, 1, 1 << 4); // OBP1
+        set_sprite(&mut ppu, 1, 16, 16, 1, 0x00);
+        set_sprite(&mut ppu, 2, 16, 24, 1, 1 << 7); // behind the background
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd_source[0..8], [color::OBP1_SOURCE; 8]);
+        assert_eq!(ppu.lcd_source[8..16], [color::OBP0_SOURCE; 8]);
+        assert_eq!(ppu.lcd_source[16..24], [BG_SOURCE; 8]);
+    }
+
+    #[test]
+    fn lower_x_wins_over_lower_oam_index() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 0, 16, 30, 2, 0x00);
+        set_sprite(&mut ppu, 1, 16, 25, 2, 0x10); // OBP1 turns color 3 into shade 0
+        set_sprite(&mut ppu, 2, 16, 20, 1, 0x00);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[12..20], [1; 8]); // sprite 2 (x = 20) on top of everything
+        assert_eq!(ppu.lcd[20..25], [0; 5]); // then sprite 1 (x = 25)
+        assert_eq!(ppu.lcd[25..30], [3; 5]); // and finally sprite 0 (x = 30)
+    }
+
+    #[test]
+    fn offscreen_sprites_count_towards_limit() {
+        let mut ppu = setup_ppu();
+        for i in 0..10 {
+            set_sprite(&mut ppu, i, 16, 0, 2, 0x00);
+        }
+        set_sprite(&mut ppu, 10, 16, 20, 2, 0x00);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[0..160], [0; 160]);
+    }
+
+    #[test]
+    fn partially_offscreen_sprite_is_clipped() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 0, 16, 4, 3, 0x00);
+        run_frames(&mut ppu, 2);
+
+        assert_eq!(ppu.lcd[0..8], [0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    fn mode3_length(ppu: &PPU, ly: usize) -> usize {
+        ppu.debug_panel[(ly * 3) + 1] - ppu.debug_panel[ly * 3]
+    }
+
+    #[test]
+    fn mode3_length_without_penalties() {
+        let mut ppu = setup_ppu();
+        run_frames(&mut ppu, 2);
+        assert_eq!(mode3_length(&ppu, 10), 172);
+        assert_eq!(ppu.debug_panel[(10 * 3) + 2], 456);
+    }
+
+    #[test]
+    fn mode3_length_with_fine_scroll() {
+        for scx in 0..16 {
+            let mut ppu = setup_ppu();
+            ppu.scx = scx;
+            run_frames(&mut ppu, 2);
+            assert_eq!(mode3_length(&ppu, 10), 172 + (scx as usize % 8));
+        }
+    }
+
+    #[test]
+    fn mode3_length_with_window() {
+        let mut ppu = setup_ppu();
+        ppu.control |= 1 << WINDOW_ENABLED;
+        ppu.wy = 0;
+        ppu.wx = 87;
+        run_frames(&mut ppu, 2);
+        assert_eq!(mode3_length(&ppu, 10), 172 + 6);
+    }
+
+    #[test]
+    fn mode3_length_with_sprites() {
+        // (x, scx, penalty) from the first sprite on a tile waiting out the background fetch
+        for (x_pos, scx, penalty) in [(0, 0, 11), (8, 0, 11), (9, 0, 10), (12, 0, 7), (13, 0, 6), (15, 0, 6), (8, 3, 8), (8, 5, 6), (168, 0, 0)] {
+            let mut ppu = setup_ppu();
+            ppu.scx = scx;
+            set_sprite(&mut ppu, 0, 16, x_pos, 1, 0x00);
+            run_frames(&mut ppu, 2);
+            assert_eq!(mode3_length(&ppu, 0), 172 + (scx as usize % 8) + penalty, "sprite at x = {} with scx = {}", x_pos, scx);
+        }
+    }
+
+    #[test]
+    fn mode3_length_with_sprites_sharing_a_tile() {
+        let mut ppu = setup_ppu();
+        set_sprite(&mut ppu, 0, 16, 8, 1, 0x00);
+        set_sprite(&mut ppu, 1, 16, 8, 1, 0x00);
+        set_sprite(&mut ppu, 2, 16, 20, 1, 0x00);
+        run_frames(&mut ppu, 2);
+        assert_eq!(mode3_length(&ppu, 0), 172 + 11 + 6 + 6 + 1);
+    }
+
+    // tile 0 in the signed addressing block is solid color 3 and the map is all tile 0
+    fn fill_background(ppu: &mut PPU) {
+        ppu.vram[0x1000..0x1010].fill(0xFF);
+    }
+
+    fn run_dots(ppu: &mut PPU, dots: usize) {
+        for _ in 0..(dots / 4) {
+            ppu.update();
+        }
+    }
+
+    #[test]
+    fn disabling_lcd_resets_ly_and_mode() {
+        let mut ppu = setup_ppu();
+        fill_background(&mut ppu);
+        run_frames(&mut ppu, 2);
+        run_dots(&mut ppu, (50 * 456) + 200); // middle of mode 3 on line 50
+
+        assert_eq!(ppu.get_mode(), Mode::DRAW);
+        assert_eq!(ppu.lcd[0], 3);
+
+        ppu.write_registers(0xFF40, 0b00000011);
+        assert_eq!(ppu.ly, 0);
+        assert_eq!(ppu.get_mode(), Mode::HBLANK);
+        assert_eq!(ppu.lcd, [0; 23040]);
+
+        run_dots(&mut ppu, 1000);
+        assert_eq!(ppu.ly, 0);
+        assert_eq!(ppu.get_mode(), Mode::HBLANK);
+    }
+
+    #[test]
+    fn enabling_lcd_skips_first_oam_scan() {
+        let mut ppu = setup_ppu();
+        assert_eq!(ppu.get_mode(), Mode::HBLANK);
+
+        run_dots(&mut ppu, 76);
+        assert_eq!(ppu.get_mode(), Mode::HBLANK);
+        run_dots(&mut ppu, 4);
+        assert_eq!(ppu.get_mode(), Mode::DRAW);
+        assert_eq!(ppu.ly, 0);
+
+        run_dots(&mut ppu, 456 - 80);
+        assert_eq!(ppu.get_mode(), Mode::OAMSCAN);
+        assert_eq!(ppu.ly, 1);
+    }
+
+    #[test]
+    fn first_frame_after_enabling_lcd_is_blank() {
+        let mut ppu = setup_ppu();
+        fill_background(&mut ppu);
+
+        run_frames(&mut ppu, 1);
+        assert!(ppu.rendered_frame);
+        assert_eq!(ppu.lcd, [0; 23040]);
+
+        run_frames(&mut ppu, 1);
+        assert_eq!(ppu.lcd, [3; 23040]);
+    }
+
+    #[test]
+    fn toggling_lcd_mid_frame() {
+        let mut ppu = setup_ppu();
+        fill_background(&mut ppu);
+        run_frames(&mut ppu, 2);
+
+        for dots in [0, 80, 3000, (100 * 456) + 100, (150 * 456) + 300] {
+            run_dots(&mut ppu, dots);
+            ppu.write_registers(0xFF40, 0b00000011);
+            run_dots(&mut ppu, 400);
+            ppu.write_registers(0xFF40, 0b10000011);
+
+            run_frames(&mut ppu, 1);
+            assert_eq!(ppu.lcd, [0; 23040]);
+            assert_eq!(ppu.ly, 0);
+            run_frames(&mut ppu, 1);
+            assert_eq!(ppu.lcd, [3; 23040]);
+            assert_eq!(ppu.ly, 0);
+        }
+    }
+
+    #[test]
+    fn coincidence_flag_follows_ly_through_power_cycle() {
+        let mut ppu = setup_ppu();
+        ppu.write_registers(0xFF45, 0);
+        assert_eq!((ppu.stat >> 2) & 0x1, 1);
+
+        run_dots(&mut ppu, 456);
+        assert_eq!((ppu.stat >> 2) & 0x1, 0);
+
+        ppu.write_registers(0xFF40, 0b00000011); // LY goes back to 0
+        assert_eq!((ppu.stat >> 2) & 0x1, 1);
+
+        ppu.write_registers(0xFF40, 0b10000011);
+        run_frames(&mut ppu, 1); // wraps around from line 153
+        assert_eq!(ppu.ly, 0);
+        assert_eq!((ppu.stat >> 2) & 0x1, 1);
+    }
+
+    #[test]
+    fn frames_are_paced_while_lcd_is_off() {
+        let mut ppu = PPU::default();
+        run_dots(&mut ppu, DOTS_PER_FRAME - 4);
+        assert!(!ppu.rendered_frame);
+        run_dots(&mut ppu, 4);
+        assert!(ppu.rendered_frame);
+    }
+
+    // runs a frame and returns where STAT was requested as (LY, dot, mode)
+    fn stat_requests(ppu: &mut PPU) -> Vec<(u8, u16, Mode)> {
+        ppu.take_requested_interrupts(); // drop whatever was raised before
+        let mut requests = vec![];
+        for _ in 0..(DOTS_PER_FRAME / 4) {
+            ppu.update();
+            if ppu.take_requested_interrupts() & 0b10 != 0 {
+                let (ly, dot) = ppu.get_position();
+                requests.push((ly, dot, ppu.get_mode()));
+            }
+        }
+        requests
+    }
+
+    #[test]
+    fn hblank_source_fires_once_per_line() {
+        let mut ppu = setup_ppu();
+        ppu.write_registers(0xFF41, 1 << 3);
+        run_frames(&mut ppu, 2);
+
+        let requests = stat_requests(&mut ppu);
+        assert_eq!(requests.len(), 144);
+        assert!(requests.iter().all(|(_, dot, mode)| *mode == Mode::HBLANK && *dot == 252));
+    }
+
+    #[test]
+    fn stat_blocking_between_hblank_and_oam() {
+        let mut ppu = setup_ppu();
+        ppu.write_registers(0xFF41, (1 << 5) | (1 << 3));
+        run_frames(&mut ppu, 2);
+
+        // the line never drops between HBLANK and the next OAM scan so only line 0 gets an OAM interrupt
+        let requests = stat_requests(&mut ppu);
+        assert_eq!(requests.len(), 145);
+        assert_eq!(requests.iter().filter(|(_, _, mode)| *mode == Mode::OAMSCAN).count(), 1);
+    }
+
+    #[test]
+    fn writing_lyc_mid_line_requests_interrupt() {
+        let mut ppu = setup_ppu();
+        ppu.write_registers(0xFF45, 100);
+        ppu.write_registers(0xFF41, 1 << 6);
+        run_frames(&mut ppu, 2);
+        run_dots(&mut ppu, (10 * 456) + 100);
+        ppu.take_requested_interrupts();
+
+        ppu.write_registers(0xFF45, 10);
+        assert_eq!(ppu.take_requested_interrupts(), 0b10);
+
+        ppu.write_registers(0xFF45, 10); // already high
+        assert_eq!(ppu.take_requested_interrupts(), 0);
+    }
+
+    #[test]
+    fn lyc_matches_on_line_153() {
+        let mut ppu = setup_ppu();
+        ppu.write_registers(0xFF41, 1 << 6);
+
+        ppu.write_registers(0xFF45, 153);
+        run_frames(&mut ppu, 2);
+        assert_eq!(stat_requests(&mut ppu), [(153, 0, Mode::VBLANK)]);
+
+        // LY turns 0 a few dots into line 153 and stays there through line 0, so there is no second edge
+        ppu.write_registers(0xFF45, 0);
+        assert_eq!(stat_requests(&mut ppu), [(0, 4, Mode::VBLANK)]);
+    }
+
+    #[test]
+    fn vblank_interrupt_requested_once_per_frame() {
+        let mut ppu = setup_ppu();
+        run_frames(&mut ppu, 2);
+        ppu.take_requested_interrupts();
+
+        let mut requests = 0;
+        for _ in 0..(DOTS_PER_FRAME / 4) {
+            ppu.update();
+            if ppu.take_requested_interrupts() & 0b1 != 0 {
+                requests += 1;
+                assert_eq!(ppu.get_position(), (144, 0));
+            }
+        }
+        assert_eq!(requests, 1);
+    }
+
+    #[test]
+    fn run_matches_updating_every_cycle() {
+        let mut stepped = setup_ppu();
+        let mut skipped = setup_ppu();
+        for ppu in [&mut stepped, &mut skipped] {
+            fill_background(ppu);
+            set_sprite(ppu, 0, 40, 30, 2, 0x00);
+            for i in 1..13 { // more than the OAM scan can pick up on those lines
+                set_sprite(ppu, i, 60 + i as u8, 10 * i as u8, 1 + (i % 3) as u8, 0x00);
+            }
+            ppu.write_registers(0xFF41, (1 << 6) | (1 << 4) | (1 << 3));
+            ppu.write_registers(0xFF45, 100);
+        }
+
+        let mut chunks = vec![1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144, 233, 377, 610, 987, 1597];
+        chunks.extend_from_slice(&[DOTS_PER_FRAME / 4; 3]);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            if i == 10 { // the LCD being off is skipped through too
+                stepped.write_registers(0xFF40, 0b00000011);
+                skipped.write_registers(0xFF40, 0b00000011);
+            } else if i == 12 {
+                stepped.write_registers(0xFF40, 0b10000011);
+                skipped.write_registers(0xFF40, 0b10000011);
+            }
+
+            for _ in 0..chunk {
+                stepped.update();
+            }
+            skipped.run(chunk);
+
+            assert_eq!(skipped.get_position(), stepped.get_position());
+            assert_eq!(skipped.stat, stepped.stat);
+            assert_eq!(skipped.vblank_timeline, stepped.vblank_timeline);
+            assert_eq!(skipped.off_timeline, stepped.off_timeline);
+            assert_eq!(skipped.rendered_frame, stepped.rendered_frame);
+            assert_eq!(skipped.take_requested_interrupts(), stepped.take_requested_interrupts());
+            assert_eq!(skipped.lcd, stepped.lcd);
+        }
+    }
+
+    // same scene drawn by both renderers, returns (fifo, scanline)
+    fn render_both(scene: impl Fn(&mut PPU)) -> (PPU, PPU) {
+        let mut fifo = setup_ppu();
+        let mut scanline = setup_ppu();
+        scanline.set_renderer(1);
+        for ppu in [&mut fifo, &mut scanline] {
+            for (i, byte) in ppu.vram[0x0400..0x0800].iter_mut().enumerate() { // tiles 64-127: noisy patterns
+                *byte = (i as u8).wrapping_mul(37) ^ (i as u8 >> 3);
+            }
+            for (i, tile) in ppu.vram[0x1800..0x2000].iter_mut().enumerate() { // both maps
+                *tile = 64 + (i % 64) as u8;
+            }
+            ppu.control |= 1 << TILE_ADDRESSING;
+            scene(ppu);
+        }
+        run_frames(&mut fifo, 3);
+        run_frames(&mut scanline, 3);
+        (fifo, scanline)
+    }
+
+    fn assert_same_output(scene: impl Fn(&mut PPU)) {
+        let (fifo, scanline) = render_both(scene);
+        for (ly, (expected, actual)) in fifo.lcd.chunks(160).zip(scanline.lcd.chunks(160)).enumerate() {
+            assert_eq!(actual, expected, "scanline {}", ly);
+        }
+    }
+
+    #[test]
+    fn scanline_renderer_matches_fifo_background() {
+        assert_same_output(|_| {});
+        assert_same_output(|ppu| {
+            ppu.scx = 13;
+            ppu.scy = 250;
+        });
+        assert_same_output(|ppu| ppu.control &= !(1 << BG_OR_WINDOW_ENABLED));
+    }
+
+    #[test]
+    fn scanline_renderer_matches_fifo_window() {
+        for (wx, wy) in [(7, 0), (0, 20), (90, 100), (166, 0), (167, 0)] {
+            assert_same_output(|ppu| {
+                ppu.control |= (1 << WINDOW_ENABLED) | (1 << WINDOW_TILE_MAP);
+                ppu.scx = 5;
+                ppu.wx = wx;
+                ppu.wy = wy;
+            });
+        }
+    }
+
+    #[test]
+    fn scanline_renderer_matches_fifo_sprites() {
+        assert_same_output(|ppu| {
+            set_sprite(ppu, 0, 16, 20, 64, 0x00);
+            set_sprite(ppu, 1, 16, 24, 65, 1 << 5); // overlapping, x flip
+            set_sprite(ppu, 2, 20, 24, 66, 1 << 6); // lower OAM index loses to lower x, y flip
+            set_sprite(ppu, 3, 30, 4, 67, 1 << 4); // clipped on the left, OBP1
+            set_sprite(ppu, 4, 30, 164, 68, 0x00); // clipped on the right
+            set_sprite(ppu, 5, 50, 60, 69, 1 << 7); // behind background colors 1-3
+        });
+        assert_same_output(|ppu| {
+            ppu.control |= 1 << SPRITE_SIZE;
+            set_sprite(ppu, 0, 16, 40, 71, 0x00); // bottom bit of the tile number is ignored
+            set_sprite(ppu, 1, 24, 44, 72, (1 << 6) | (1 << 5));
+        });
+        assert_same_output(|ppu| { // 10 sprites per line limit
+            for i in 0..12 {
+                set_sprite(ppu, i, 40, 10 + (i as u8 * 12), 64 + i as u8, 0x00);
+            }
+        });
+    }
+
+    #[test]
+    fn scanline_renderer_skips_draw_when_running() {
+        let mut stepped = setup_ppu();
+        let mut skipped = setup_ppu();
+        for ppu in [&mut stepped, &mut skipped] {
+            ppu.set_renderer(1);
+            fill_background(ppu);
+            set_sprite(ppu, 0, 40, 30, 2, 0x00);
+            ppu.write_registers(0xFF41, 1 << 3);
+        }
+
+        for chunk in [1, 7, 50, 333, DOTS_PER_FRAME / 4, DOTS_PER_FRAME / 4] {
+            for _ in 0..chunk {
+                stepped.update();
+            }
+            skipped.run(chunk);
+
+            assert_eq!(skipped.get_position(), stepped.get_position());
+            assert_eq!(skipped.stat, stepped.stat);
+            assert_eq!(skipped.take_requested_interrupts(), stepped.take_requested_interrupts());
+            assert_eq!(skipped.lcd, stepped.lcd);
+        }
+    }
+
+    // cargo test --release ppu_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn ppu_benchmark() {
+        for renderer in [0, 1] {
+            let mut stepped = setup_ppu();
+            let mut skipped = setup_ppu();
+            for ppu in [&mut stepped, &mut skipped] {
+                fill_background(ppu);
+                for i in 0..40 {
+                    set_sprite(ppu, i, 16 + (i as u8 * 3), 8 + (i as u8 * 4), 1 + (i % 3) as u8, 0x00);
+                }
+                ppu.set_renderer(renderer);
+            }
+
+            let frames = 300;
+            let start = std::time::Instant::now();
+            run_frames(&mut stepped, frames);
+            let update_time = start.elapsed();
+
+            let start = std::time::Instant::now();
+            let mut cycles = frames * DOTS_PER_FRAME / 4;
+            while cycles > 0 { // the way Memory::catch_up drives it when nothing touches the PPU in between
+                let chunk = skipped.cycles_until_event().min(cycles);
+                skipped.run(chunk);
+                cycles -= chunk;
+            }
+            let run_time = start.elapsed();
+
+            assert_eq!(skipped.lcd, stepped.lcd);
+            println!("{:?} renderer, {} frames: update() every cycle {:?}, run() {:?}", skipped.renderer, frames, update_time, run_time);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::internal::ppu::{PPU, ObjectPixel, BG_OR_WINDOW_ENABLED, BG_TILE_MAP, SPRITES_ENABLED, SPRITE_SIZE, TILE_ADDRESSING, WINDOW_ENABLED, WINDOW_TILE_MAP};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Renderer {
+    Fifo, // pixel FIFO stepped every dot, mid-scanline register writes show up where they happen
+    Scanline // whole scanline drawn at the start of mode 3 from the registers at that point, much cheaper
+}
+
+impl PPU {
+    pub fn set_renderer(&mut self, renderer: u8) { // 0 - FIFO | 1 - scanline
+        self.renderer = if renderer == 1 { Renderer::Scanline } else { Renderer::Fifo };
+    }
+
+    fn tile_row_addr(&self, tile_number: u8, row: u16) -> usize { // offset into VRAM
+        if (self.control >> TILE_ADDRESSING) & 0x1 == 1 {
+            (tile_number as usize * 16) + (row as usize * 2)
+        } else {
+            (0x1000 + ((tile_number as i8 as i32) * 16) + (row as i32 * 2)) as usize
+        }
+    }
+
+    fn map_color_id(&self, tile_map: usize, x: u16, y: u16) -> u8 {
+        let tile_number = self.vram[tile_map + (((y / 8) * 32) + (x / 8)) as usize];
+        let addr = self.tile_row_addr(tile_number, y % 8);
+        let bit = 7 - (x % 8);
+        (((self.vram[addr + 1] >> bit) & 0x1) << 1) | ((self.vram[addr] >> bit) & 0x1)
+    }
+
+    fn sprite_pixel(&self, x: usize) -> Option<ObjectPixel> {
+        let sprite_height = if (self.control >> SPRITE_SIZE) & 0x1 == 1 { 16 } else { 8 };
+
+        // buffer is sorted by priority so the first opaque pixel wins, same as mixing in the FIFO
+        for i in 0..self.sprite_buffer.len() {
+            let sprite = self.sprite_buffer.get(i);
+            let left = sprite.x_pos as usize; // screen x + 8
+            if x + 8 < left || x + 8 >= left + 8 {
+                continue
+            }
+
+            let mut row = (self.ly as u16 + 16).wrapping_sub(sprite.y_pos as u16) % sprite_height;
+            if (sprite.sprite_flags >> 6) & 0x1 == 1 {
+                row = sprite_height - 1 - row;
+            }
+            let addr = (sprite.tile_number as usize * 16) + (row as usize * 2);
+
+            let col = x + 8 - left;
+            let bit = if (sprite.sprite_flags >> 5) & 0x1 == 1 { col } else { 7 - col };
+            let color_id = (((self.vram[addr + 1] >> bit) & 0x1) << 1) | ((self.vram[addr] >> bit) & 0x1);
+            if color_id != 0 {
+                return Some(ObjectPixel { color_id, flags: sprite.sprite_flags, x_pos: sprite.x_pos, oam_index: sprite.oam_index });
+            }
+        }
+        None
+    }
+
+    // draws the current scanline in one go, returns an estimate of how many dots mode 3 takes
+    pub(super) fn render_scanline(&mut self) -> usize {
+        let bg_map = if (self.control >> BG_TILE_MAP) & 0x1 == 1 { 0x1C00 } else { 0x1800 };
+        let window_map = if (self.control >> WINDOW_TILE_MAP) & 0x1 == 1 { 0x1C00 } else { 0x1800 };
+        let bg_enabled = (self.control >> BG_OR_WINDOW_ENABLED) & 0x1 == 1;
+        let sprites_enabled = (self.control >> SPRITES_ENABLED) & 0x1 == 1;
+        let window_start = if self.window_in_frame && (self.control >> WINDOW_ENABLED) & 0x1 == 1 && self.wx < 167 {
+            Some((self.wx as usize).saturating_sub(7))
+        } else {
+            None
+        };
+
+        for x in 0..160 {
+            self.tick_state.scanline_x = x;
+
+            let bg_color_id = match window_start {
+                Some(start) if x >= start => {
+                    self.tick_state.is_fetching_window = true;
+                    self.rendered_window_on_scanline = true;
+                    if bg_enabled { self.map_color_id(window_map, (x - start) as u16, self.window_line_counter as u16) } else { 0 }
+                },
+                _ => {
+                    let bg_x = (x as u16 + self.scx as u16) & 0xFF;
+                    let bg_y = (self.ly as u16 + self.scy as u16) & 0xFF;
+                    if bg_enabled { self.map_color_id(bg_map, bg_x, bg_y) } else { 0 }
+                }
+            };
+            let sprite = if sprites_enabled { self.sprite_pixel(x) } else { None };
+
+            self.push_pixel(bg_color_id, sprite);
+        }
+
+        let mut dots = 172 + (self.scx % 8) as usize;
+        if window_start.is_some() {
+            dots += 6;
+        }
+        if sprites_enabled {
+            for i in 0..self.sprite_buffer.len() {
+                let sprite = self.sprite_buffer.get(i);
+                if (sprite.x_pos as usize) < 168 {
+                    dots += 6 + 5usize.saturating_sub((sprite.x_pos as usize + self.scx as usize) % 8);
+                }
+            }
+        }
+        dots
+    }
+}
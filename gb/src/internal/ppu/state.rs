@@ -0,0 +1,172 @@
+use crate::internal::bess::BessError;
+use crate::internal::ppu::{PPU, Mode, LcdState, Object, ObjectPixel, TickState, DOTS_PER_FRAME};
+use crate::internal::ppu::scanline::Renderer;
+use crate::internal::state::{check, StateReader, StateWriter};
+
+fn save_object(object: &Object, state: &mut StateWriter) {
+    state.write_bytes(&[object.y_pos, object.x_pos, object.tile_number, object.sprite_flags, object.oam_index]);
+}
+
+fn load_object(state: &mut StateReader) -> Result<Object, BessError> {
+    let bytes = state.read_bytes(5)?;
+    check(bytes[4] < 40, "sprite OAM index")?;
+    Ok(Object { y_pos: bytes[0], x_pos: bytes[1], tile_number: bytes[2], sprite_flags: bytes[3], oam_index: bytes[4] })
+}
+
+impl PPU {
+    fn save_tick_state(&self, state: &mut StateWriter) {
+        let tick_state = &self.tick_state;
+        state.write_bool(tick_state.is_fetching_window);
+        state.write_usize(tick_state.fetcher_x);
+        state.write_usize(tick_state.scanline_x);
+        state.write_bytes(&[tick_state.tile_number, tick_state.tile_data_low, tick_state.tile_data_high]);
+        state.write_bool(tick_state.current_sprite.is_some());
+        save_object(&tick_state.current_sprite.unwrap_or_default(), state);
+        state.write_bool(tick_state.new_scanline);
+        state.write_usize(tick_state.oam_ptr);
+        state.write_bytes(&[tick_state.bg_fetcher_step, tick_state.sprite_fetcher_step, tick_state.pixels_to_discard]);
+        state.write_usize(tick_state.draw_dots_left);
+    }
+
+    fn load_tick_state(state: &mut StateReader) -> Result<TickState, BessError> {
+        let is_fetching_window = state.read_bool()?;
+        let fetcher_x = state.read_index(32, "fetcher X")?;
+        let scanline_x = state.read_index(160, "scanline X")?;
+        let tile = state.read_bytes(3)?;
+        let has_sprite = state.read_bool()?;
+        let sprite = load_object(state)?;
+        let new_scanline = state.read_bool()?;
+        let oam_ptr = state.read_index(39, "OAM scan pointer")?;
+        let steps = state.read_bytes(3)?;
+        check(steps[0] <= 6 && steps[2] <= 7, "fetcher step")?;
+        check(steps[1] <= 11 && (!has_sprite || steps[1] > 0), "sprite fetcher step")?;
+
+        Ok(TickState {
+            is_fetching_window,
+            fetcher_x,
+            scanline_x,
+            tile_number: tile[0],
+            tile_data_low: tile[1],
+            tile_data_high: tile[2],
+            current_sprite: if has_sprite { Some(sprite) } else { None },
+            new_scanline,
+            oam_ptr,
+            bg_fetcher_step: steps[0],
+            sprite_fetcher_step: steps[1],
+            pixels_to_discard: steps[2],
+            draw_dots_left: state.read_index(456, "scanline renderer dots")?
+        })
+    }
+
+    // everything but the debugging/frontend settings (layers, renderer, colors)
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&self.lcd);
+        state.write_bytes(&self.lcd_source);
+        state.write_bytes(&[self.control, self.stat, self.ly, self.lyc, self.scy, self.scx, self.wy, self.wx, self.bgp, self.obp0, self.obp1]);
+
+        state.write_usize(self.scanline_timeline);
+        state.write_usize(self.vblank_timeline);
+        state.write_bool(self.window_in_frame);
+        state.write_usize(self.window_line_counter);
+        state.write_bool(self.rendered_window_on_scanline);
+        state.write_bool(self.rendered_frame);
+        state.write_u8(match self.lcd_state {
+            LcdState::Off => 0,
+            LcdState::FirstFrame => 1,
+            LcdState::On => 2
+        });
+        state.write_usize(self.off_timeline);
+        state.write_bool(self.stat_line);
+        state.write_u8(self.requested_interrupts);
+        self.save_tick_state(state);
+
+        state.write_usize(self.sprite_fifo.len());
+        for i in 0..self.sprite_fifo.len() {
+            let pixel = self.sprite_fifo.get(i);
+            state.write_bytes(&[pixel.color_id, pixel.flags, pixel.x_pos, pixel.oam_index]);
+        }
+        state.write_usize(self.background_fifo.len());
+        for i in 0..self.background_fifo.len() {
+            state.write_u8(self.background_fifo.get(i));
+        }
+        state.write_usize(self.sprite_buffer.len());
+        for i in 0..self.sprite_buffer.len() {
+            save_object(&self.sprite_buffer.get(i), state);
+        }
+    }
+
+    // decoded into a separate PPU so a corrupted state can't leave this one half loaded, see restore_state
+    pub fn load_state(state: &mut StateReader) -> Result<Box<PPU>, BessError> {
+        let mut ppu = Box::<PPU>::default(); // too big to be moved around on the stack
+        ppu.vram.copy_from_slice(state.read_bytes(0x2000)?);
+        ppu.oam.copy_from_slice(state.read_bytes(0xA0)?);
+        ppu.lcd.copy_from_slice(state.read_bytes(23040)?);
+        ppu.lcd_source.copy_from_slice(state.read_bytes(23040)?);
+        check(ppu.lcd.iter().all(|shade| *shade <= 3), "LCD shade")?;
+        check(ppu.lcd_source.iter().all(|source| *source <= 2), "LCD palette source")?;
+        let registers = state.read_bytes(11)?;
+        [ppu.control, ppu.stat, ppu.ly, ppu.lyc, ppu.scy, ppu.scx, ppu.wy, ppu.wx, ppu.bgp, ppu.obp0, ppu.obp1] = registers.try_into().unwrap();
+
+        ppu.scanline_timeline = state.read_index(455, "scanline dot")?;
+        ppu.vblank_timeline = state.read_index(4559, "VBLANK dot")?;
+        ppu.window_in_frame = state.read_bool()?;
+        ppu.window_line_counter = state.read_index(144, "window line")?;
+        ppu.rendered_window_on_scanline = state.read_bool()?;
+        ppu.rendered_frame = state.read_bool()?;
+        ppu.lcd_state = match state.read_u8()? {
+            0 => LcdState::Off,
+            1 => LcdState::FirstFrame,
+            2 => LcdState::On,
+            _ => return Err(BessError::InvalidState("LCD state"))
+        };
+        ppu.off_timeline = state.read_index(DOTS_PER_FRAME - 1, "LCD off dot")?;
+        ppu.stat_line = state.read_bool()?;
+        ppu.requested_interrupts = state.read_u8()? & 0x1F;
+        ppu.tick_state = PPU::load_tick_state(state)?;
+
+        // the timelines have to agree with LY and the mode, otherwise the PPU never gets back to a line boundary
+        let mode = ppu.get_mode();
+        if mode == Mode::VBLANK {
+            let line = ppu.vblank_timeline / 456;
+            check(ppu.scanline_timeline == ppu.vblank_timeline % 456, "VBLANK dot")?;
+            check(ppu.ly as usize == 144 + line || (ppu.ly == 0 && ppu.vblank_timeline >= (9 * 456) + 4), "LY")?;
+        } else {
+            check(ppu.ly <= 143, "LY")?;
+        }
+        check(mode != Mode::OAMSCAN || ppu.tick_state.oam_ptr == ppu.scanline_timeline / 2, "OAM scan dot")?; // one entry every 2 dots
+        check(mode != Mode::DRAW || ppu.scanline_timeline + ppu.tick_state.draw_dots_left < 456, "mode 3 length")?;
+
+        let len = state.read_index(ppu.sprite_fifo.capacity(), "sprite FIFO length")?;
+        for _ in 0..len {
+            let bytes = state.read_bytes(4)?;
+            check(bytes[0] <= 3, "sprite pixel")?;
+            ppu.sprite_fifo.push(ObjectPixel { color_id: bytes[0], flags: bytes[1], x_pos: bytes[2], oam_index: bytes[3] });
+        }
+        let len = state.read_index(ppu.background_fifo.capacity(), "background FIFO length")?;
+        for _ in 0..len {
+            let color_id = state.read_u8()?;
+            check(color_id <= 3, "background pixel")?;
+            ppu.background_fifo.push(color_id);
+        }
+        let len = state.read_index(ppu.sprite_buffer.capacity(), "sprite buffer length")?;
+        for _ in 0..len {
+            ppu.sprite_buffer.push(load_object(state)?);
+        }
+        Ok(ppu)
+    }
+
+    // takes over everything a state holds, the debugging/frontend settings stay as they are
+    pub fn restore_state(&mut self, mut loaded: Box<PPU>) {
+        std::mem::swap(&mut loaded.colors, &mut self.colors);
+        std::mem::swap(&mut loaded.layers, &mut self.layers);
+        std::mem::swap(&mut loaded.debug_panel, &mut self.debug_panel);
+        loaded.renderer = self.renderer;
+        // the FIFO renderer doesn't count down mode 3, finish it at its minimum length instead
+        if loaded.renderer == Renderer::Scanline && loaded.get_mode() == Mode::DRAW && loaded.tick_state.draw_dots_left == 0 {
+            loaded.tick_state.draw_dots_left = (80 + 172usize).saturating_sub(loaded.scanline_timeline).max(1);
+        }
+        std::mem::swap(self, &mut loaded);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::internal::ppu::{PPU, TILE_ADDRESSING, SPRITE_SIZE};
+
+// shades 0-3 from lightest to darkest (same as the web frontend)
+const SHADES: [[u8; 4]; 4] = [[0xFF, 0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA, 0xFF], [0x55, 0x55, 0x55, 0xFF], [0x00, 0x00, 0x00, 0xFF]];
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+
+pub const TILE_SHEET_WIDTH: usize = 16 * 8; // 16 tiles per row
+pub const TILE_SHEET_HEIGHT: usize = 24 * 8; // 384 tiles
+pub const TILE_MAP_SIZE: usize = 32 * 8;
+pub const OAM_SHEET_WIDTH: usize = 8 * 8; // 8 sprites per row
+pub const OAM_SHEET_HEIGHT: usize = 5 * 16; // 40 sprites, cells are tall enough for 8x16 objects
+
+pub struct SpriteAttributes {
+    pub index: u8,
+    pub y_pos: u8,
+    pub x_pos: u8,
+    pub tile_number: u8,
+    pub palette: u8, // 0 - OBP0 | 1 - OBP1
+    pub x_flip: bool,
+    pub y_flip: bool,
+    pub bg_priority: bool // BG and window colors 1-3 are drawn over the object
+}
+
+impl PPU {
+    // color ids of one row of the tile starting at `tile_addr` (offset into VRAM)
+    fn tile_row(&self, tile_addr: usize, row: usize) -> [u8; 8] {
+        let low = self.vram[tile_addr + (row * 2)];
+        let high = self.vram[tile_addr + (row * 2) + 1];
+
+        let mut pixels = [0; 8];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = (((high >> (7 - i)) & 0x1) << 1) | ((low >> (7 - i)) & 0x1);
+        }
+        pixels
+    }
+
+    fn apply_palette(palette: u8, color_id: u8) -> u8 {
+        (palette >> (color_id * 2)) & 0x3
+    }
+
+    fn put_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: [u8; 4]) {
+        let offset = ((y * width) + x) * 4;
+        buffer[offset..offset + 4].copy_from_slice(&color);
+    }
+
+    // all 384 tiles in VRAM as an RGBA image, palette: 0 - BGP | 1 - OBP0 | 2 - OBP1 | otherwise raw color ids
+    pub fn tile_sheet(&self, palette: u8) -> Vec<u8> {
+        let palette = match palette {
+            0 => self.bgp,
+            1 => self.obp0,
+            2 => self.obp1,
+            _ => 0b11100100
+        };
+
+        let mut buffer = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT * 4];
+        for tile in 0..384 {
+            let tile_x = (tile % 16) * 8;
+            let tile_y = (tile / 16) * 8;
+
+            for row in 0..8 {
+                for (col, color_id) in self.tile_row(tile * 16, row).iter().enumerate() {
+                    let shade = PPU::apply_palette(palette, *color_id);
+                    PPU::put_pixel(&mut buffer, TILE_SHEET_WIDTH, tile_x + col, tile_y + row, SHADES[shade as usize]);
+                }
+            }
+        }
+        buffer
+    }
+
+    // the 32x32 tile map at 0x9800 (map 0) or 0x9C00 (map 1) as an RGBA image with the SCX/SCY viewport outlined
+    pub fn tile_map(&self, map: u8) -> Vec<u8> {
+        let map_base: usize = if map == 0 { 0x1800 } else { 0x1C00 };
+
+        let mut buffer = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE * 4];
+        for map_y in 0..32 {
+            for map_x in 0..32 {
+                let tile_number = self.vram[map_base + (map_y * 32) + map_x];
+                let tile_addr = if (self.control >> TILE_ADDRESSING) & 0x1 == 1 {
+                    tile_number as usize * 16
+                } else {
+                    (0x1000 + ((tile_number as i8 as i32) * 16)) as usize
+                };
+
+                for row in 0..8 {
+                    for (col, color_id) in self.tile_row(tile_addr, row).iter().enumerate() {
+                        let shade = PPU::apply_palette(self.bgp, *color_id);
+                        PPU::put_pixel(&mut buffer, TILE_MAP_SIZE, (map_x * 8) + col, (map_y * 8) + row, SHADES[shade as usize]);
+                    }
+                }
+            }
+        }
+
+        // viewport wraps around the edges of the map
+        for i in 0..160 {
+            let x = (self.scx as usize + i) % TILE_MAP_SIZE;
+            PPU::put_pixel(&mut buffer, TILE_MAP_SIZE, x, self.scy as usize, VIEWPORT_COLOR);
+            PPU::put_pixel(&mut buffer, TILE_MAP_SIZE, x, (self.scy as usize + 143) % TILE_MAP_SIZE, VIEWPORT_COLOR);
+        }
+        for i in 0..144 {
+            let y = (self.scy as usize + i) % TILE_MAP_SIZE;
+            PPU::put_pixel(&mut buffer, TILE_MAP_SIZE, self.scx as usize, y, VIEWPORT_COLOR);
+            PPU::put_pixel(&mut buffer, TILE_MAP_SIZE, (self.scx as usize + 159) % TILE_MAP_SIZE, y, VIEWPORT_COLOR);
+        }
+
+        buffer
+    }
+
+    pub fn oam_sprites(&self) -> Vec<SpriteAttributes> {
+        (0..40).map(|i| {
+            let flags = self.oam[(i * 4) + 3];
+            SpriteAttributes {
+                index: i as u8,
+                y_pos: self.oam[i * 4],
+                x_pos: self.oam[(i * 4) + 1],
+                tile_number: self.oam[(i * 4) + 2],
+                palette: (flags >> 4) & 0x1,
+                x_flip: (flags >> 5) & 0x1 == 1,
+                y_flip: (flags >> 6) & 0x1 == 1,
+                bg_priority: (flags >> 7) & 0x1 == 1
+            }
+        }).collect()
+    }
+
+    // every OAM entry drawn with its own palette and flips, 8 per row in OAM order
+    pub fn oam_sheet(&self) -> Vec<u8> {
+        let tall_sprites = (self.control >> SPRITE_SIZE) & 0x1 == 1;
+
+        let mut buffer = vec![0; OAM_SHEET_WIDTH * OAM_SHEET_HEIGHT * 4];
+        for sprite in self.oam_sprites() {
+            let cell_x = (sprite.index as usize % 8) * 8;
+            let cell_y = (sprite.index as usize / 8) * 16;
+            let palette = if sprite.palette == 1 { self.obp1 } else { self.obp0 };
+            let (tile_number, height) = if tall_sprites { (sprite.tile_number & 0b11111110, 16) } else { (sprite.tile_number, 8) };
+
+            for row in 0..height {
+                let tile_row = if sprite.y_flip { height - 1 - row } else { row };
+                let pixels = self.tile_row((tile_number as usize * 16) + ((tile_row / 8) * 16), tile_row % 8);
+
+                for col in 0..8 {
+                    let color_id = pixels[if sprite.x_flip { 7 - col } else { col }];
+                    let color = if color_id == 0 { [0x00; 4] } else { SHADES[PPU::apply_palette(palette, color_id) as usize] }; // color 0 is transparent
+                    PPU::put_pixel(&mut buffer, OAM_SHEET_WIDTH, cell_x + col, cell_y + row, color);
+                }
+            }
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // identity palettes except OBP1 which is inverted
+    fn setup_ppu() -> PPU {
+        PPU {
+            bgp: 0b11100100,
+            obp0: 0b11100100,
+            obp1: 0b00011011,
+            ..PPU::default()
+        }
+    }
+
+    // fills the tile at `tile_addr` (offset into VRAM) with a single color id
+    fn fill_tile(ppu: &mut PPU, tile_addr: usize, color_id: u8) {
+        for row in 0..8 {
+            ppu.vram[tile_addr + (row * 2)] = if color_id & 0x1 == 1 { 0xFF } else { 0x00 };
+            ppu.vram[tile_addr + (row * 2) + 1] = if color_id & 0x2 == 2 { 0xFF } else { 0x00 };
+        }
+    }
+
+    fn pixel(buffer: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        let offset = ((y * width) + x) * 4;
+        buffer[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn tile_sheet_lays_out_16_tiles_per_row() {
+        let mut ppu = setup_ppu();
+        fill_tile(&mut ppu, 0, 1);
+        ppu.vram[17 * 16] = 0x80; // tile 17: color 3 in the top left corner only
+        ppu.vram[(17 * 16) + 1] = 0x80;
+        ppu.vram[383 * 16] = 0xFF; // last tile: top row in color 1
+
+        let sheet = ppu.tile_sheet(0);
+        assert_eq!(sheet.len(), TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT * 4);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 7, 7), SHADES[1]);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 8, 0), SHADES[0]);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 8, 8), SHADES[3]);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 9, 8), SHADES[0]);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 127, 184), SHADES[1]);
+        assert_eq!(pixel(&sheet, TILE_SHEET_WIDTH, 127, 185), SHADES[0]);
+    }
+
+    #[test]
+    fn tile_sheet_uses_the_selected_palette() {
+        let mut ppu = setup_ppu();
+        fill_tile(&mut ppu, 0, 1);
+
+        assert_eq!(pixel(&ppu.tile_sheet(2), TILE_SHEET_WIDTH, 0, 0), SHADES[2]); // OBP1
+        ppu.bgp = 0x00;
+        assert_eq!(pixel(&ppu.tile_sheet(0), TILE_SHEET_WIDTH, 0, 0), SHADES[0]);
+        assert_eq!(pixel(&ppu.tile_sheet(3), TILE_SHEET_WIDTH, 0, 0), SHADES[1]); // raw color ids
+    }
+
+    #[test]
+    fn tile_map_follows_lcdc_tile_addressing() {
+        let mut ppu = setup_ppu();
+        ppu.scx = 100; // keep the viewport away from the checked tiles
+        ppu.scy = 100;
+        fill_tile(&mut ppu, 0x0010, 3); // tile 1 at 0x8000
+        fill_tile(&mut ppu, 0x1010, 1); // tile 1 at 0x9000
+        fill_tile(&mut ppu, 0x0800, 2); // tile 0x80 in both blocks
+        ppu.vram[0x1800] = 0x01;
+        ppu.vram[0x1801] = 0x80;
+        ppu.vram[0x1C00] = 0x01;
+
+        ppu.control = 1 << TILE_ADDRESSING; // unsigned from 0x8000
+        let map = ppu.tile_map(0);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 4, 4), SHADES[3]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 12, 4), SHADES[2]);
+
+        ppu.control = 0; // signed from 0x9000
+        let map = ppu.tile_map(0);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 4, 4), SHADES[1]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 12, 4), SHADES[2]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 20, 4), SHADES[0]); // tile 0 at 0x9000 is empty
+
+        assert_eq!(pixel(&ppu.tile_map(1), TILE_MAP_SIZE, 4, 4), SHADES[1]);
+        assert_eq!(pixel(&ppu.tile_map(1), TILE_MAP_SIZE, 12, 4), SHADES[0]);
+    }
+
+    #[test]
+    fn tile_map_viewport_wraps_around() {
+        let mut ppu = setup_ppu();
+        ppu.scx = 200;
+        ppu.scy = 250;
+        let map = ppu.tile_map(0);
+
+        // top and bottom edges
+        for x in (200..256).chain(0..104) {
+            assert_eq!(pixel(&map, TILE_MAP_SIZE, x, 250), VIEWPORT_COLOR, "x {}", x);
+            assert_eq!(pixel(&map, TILE_MAP_SIZE, x, 137), VIEWPORT_COLOR, "x {}", x);
+        }
+        // left and right edges
+        for y in (250..256).chain(0..138) {
+            assert_eq!(pixel(&map, TILE_MAP_SIZE, 200, y), VIEWPORT_COLOR, "y {}", y);
+            assert_eq!(pixel(&map, TILE_MAP_SIZE, 103, y), VIEWPORT_COLOR, "y {}", y);
+        }
+
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 104, 250), SHADES[0]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 199, 0), SHADES[0]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 0, 138), SHADES[0]);
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 150, 100), SHADES[0]); // outside the viewport
+        assert_eq!(pixel(&map, TILE_MAP_SIZE, 50, 50), SHADES[0]); // inside it
+    }
+
+    #[test]
+    fn oam_sprites_decode_attributes() {
+        let mut ppu = setup_ppu();
+        ppu.oam[39 * 4..40 * 4].copy_from_slice(&[0x10, 0x08, 0x42, 0b11110000]);
+
+        let sprites = ppu.oam_sprites();
+        assert_eq!(sprites.len(), 40);
+        let sprite = &sprites[39];
+        assert_eq!((sprite.index, sprite.y_pos, sprite.x_pos, sprite.tile_number, sprite.palette), (39, 0x10, 0x08, 0x42, 1));
+        assert!(sprite.x_flip && sprite.y_flip && sprite.bg_priority);
+        assert!(!sprites[0].x_flip && !sprites[0].y_flip && !sprites[0].bg_priority);
+    }
+
+    #[test]
+    fn oam_sheet_draws_8x8_sprites_with_flips() {
+        let mut ppu = setup_ppu();
+        ppu.vram[0x30] = 0x80; // tile 3: color 1 in the top left corner only
+        ppu.oam[9 * 4..10 * 4].copy_from_slice(&[0, 0, 3, 0b01100000]); // x and y flipped, cell (8, 16)
+        ppu.oam[10 * 4..11 * 4].copy_from_slice(&[0, 0, 3, 0b00010000]); // OBP1, cell (16, 16)
+
+        let sheet = ppu.oam_sheet();
+        assert_eq!(sheet.len(), OAM_SHEET_WIDTH * OAM_SHEET_HEIGHT * 4);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 15, 23), SHADES[1]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 8, 16), [0x00; 4]); // color 0 is transparent
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 16, 16), SHADES[2]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 16, 24), [0x00; 4]); // bottom half of the cell is unused
+    }
+
+    #[test]
+    fn oam_sheet_draws_8x16_sprites() {
+        let mut ppu = setup_ppu();
+        ppu.control = 1 << SPRITE_SIZE;
+        fill_tile(&mut ppu, 0x20, 1); // tile 2: top half
+        fill_tile(&mut ppu, 0x30, 2); // tile 3: bottom half
+        ppu.oam[0..4].copy_from_slice(&[0, 0, 3, 0]); // bit 0 of the tile number is ignored
+        ppu.oam[4..8].copy_from_slice(&[0, 0, 2, 0b01000000]); // y flipped
+
+        let sheet = ppu.oam_sheet();
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 0, 0), SHADES[1]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 0, 7), SHADES[1]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 0, 8), SHADES[2]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 7, 15), SHADES[2]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 8, 0), SHADES[2]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 8, 15), SHADES[1]);
+
+        ppu.control = 0; // the same entries as 8x8 sprites
+        let sheet = ppu.oam_sheet();
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 0, 0), SHADES[2]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 0, 8), [0x00; 4]);
+        assert_eq!(pixel(&sheet, OAM_SHEET_WIDTH, 8, 0), SHADES[1]);
+    }
+}
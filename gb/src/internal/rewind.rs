@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use crate::internal::core::component::CPU;
+
+const MAX_RUN: usize = 0xFFFF;
+
+// runs of unchanged (zero) bytes followed by literal bytes: [zeros: u16][literals: u16][literal bytes]..
+// `older` is xor'd against `newer` (missing bytes count as 0) and the length of `older` goes in front
+fn encode_delta(older: &[u8], newer: &[u8], delta: &mut Vec<u8>) {
+    delta.clear();
+    delta.extend_from_slice(&(older.len() as u32).to_le_bytes());
+
+    let len = older.len().max(newer.len());
+    let xor = |i: usize| older.get(i).copied().unwrap_or(0) ^ newer.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < len {
+        let mut zeros = 0;
+        while i < len && zeros < MAX_RUN && xor(i) == 0 {
+            zeros += 1;
+            i += 1;
+        }
+
+        let literals_start = i;
+        while i < len && i - literals_start < MAX_RUN && xor(i) != 0 {
+            i += 1;
+        }
+
+        delta.extend_from_slice(&(zeros as u16).to_le_bytes());
+        delta.extend_from_slice(&((i - literals_start) as u16).to_le_bytes());
+        for j in literals_start..i {
+            delta.push(xor(j));
+        }
+    }
+}
+
+// turns `snapshot` (the newer one) back into the snapshot the delta was made from
+fn apply_delta(snapshot: &mut Vec<u8>, delta: &[u8]) {
+    let older_len = u32::from_le_bytes(delta[0..4].try_into().unwrap()) as usize;
+    if snapshot.len() < older_len {
+        snapshot.resize(older_len, 0);
+    }
+
+    let mut i = 0;
+    let mut ptr = 4;
+    while ptr < delta.len() {
+        let zeros = u16::from_le_bytes([delta[ptr], delta[ptr + 1]]) as usize;
+        let literals = u16::from_le_bytes([delta[ptr + 2], delta[ptr + 3]]) as usize;
+        ptr += 4;
+        i += zeros;
+        for byte in &delta[ptr..ptr + literals] {
+            snapshot[i] ^= byte;
+            i += 1;
+        }
+        ptr += literals;
+    }
+    snapshot.truncate(older_len);
+}
+
+// the latest snapshot is kept whole, older ones only as deltas going back from it
+#[derive(Default)]
+pub struct Rewind {
+    interval: usize, // frames between snapshots, 0 when rewinding is off
+    budget: usize, // bytes of deltas kept, the oldest ones are dropped past this
+    frames: usize, // since the last snapshot
+
+    latest: Vec<u8>,
+    scratch: Vec<u8>, // next snapshot
+    deltas: VecDeque<Vec<u8>>,
+    deltas_len: usize,
+    spare: Vec<u8> // allocation of the last dropped delta
+}
+
+impl Rewind {
+    pub fn configure(&mut self, interval: usize, budget: usize) {
+        self.interval = interval;
+        self.budget = budget;
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest.clear();
+        self.deltas.clear();
+        self.deltas_len = 0;
+    }
+
+    pub fn snapshots(&self) -> usize { // that can be rewound to
+        if self.latest.is_empty() { 0 } else { self.deltas.len() + 1 }
+    }
+
+    // call after every frame, takes a snapshot every `interval` frames
+    pub fn frame_done(&mut self, core: &mut CPU) {
+        if self.interval == 0 {
+            return
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return
+        }
+        self.frames = 0;
+
+        core.save_snapshot(&mut self.scratch);
+        if !self.latest.is_empty() {
+            let mut delta = std::mem::take(&mut self.spare);
+            encode_delta(&self.latest, &self.scratch, &mut delta);
+            self.deltas_len += delta.len();
+            self.deltas.push_back(delta);
+
+            while self.deltas_len > self.budget {
+                let oldest = self.deltas.pop_front().unwrap();
+                self.deltas_len -= oldest.len();
+                self.spare = oldest;
+            }
+        }
+        std::mem::swap(&mut self.latest, &mut self.scratch);
+    }
+
+    // goes back to the latest snapshot and makes the one before it the next to go back to
+    pub fn step(&mut self, core: &mut CPU) -> bool {
+        if self.latest.is_empty() {
+            return false
+        }
+
+        core.load_snapshot(&self.latest).expect("rewind snapshots come from the running cartridge");
+        self.frames = 0;
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                apply_delta(&mut self.latest, &delta);
+                self.deltas_len -= delta.len();
+                self.spare = delta;
+            },
+            None => self.latest.clear()
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn deltas_restore_the_older_snapshot() {
+        let older: Vec<u8> = (0..70000).map(|i| (i % 251) as u8).collect();
+        let mut newer = older.clone();
+        newer[10] ^= 0xFF;
+        newer[65540..65560].fill(0); // a run of zeros longer than a u16 before it
+        newer.extend_from_slice(&[1, 2, 3]);
+
+        let mut delta = vec![];
+        encode_delta(&older, &newer, &mut delta);
+        assert!(delta.len() < 100);
+
+        let mut snapshot = newer.clone();
+        apply_delta(&mut snapshot, &delta);
+        assert_eq!(snapshot, older);
+
+        encode_delta(&newer, &older, &mut delta); // shrinking
+        let mut snapshot = older.clone();
+        apply_delta(&mut snapshot, &delta);
+        assert_eq!(snapshot, newer);
+    }
+
+    #[test]
+    fn rewinding_replays_the_same_frames() {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+
+        let mut rewind = Rewind::default();
+        rewind.configure(2, usize::MAX);
+
+        let mut frames = vec![]; // frames[i] is frame i + 1
+        for _ in 0..20 {
+            frames.push(core.next_frame(-1));
+            rewind.frame_done(&mut core);
+        }
+        assert_eq!(rewind.snapshots(), 10);
+        frames.push(core.next_frame(-1));
+
+        for step in (1..=10).rev() { // snapshot taken after frame 2 * step
+            assert!(rewind.step(&mut core));
+            assert_eq!(core.frame_count as usize, step * 2);
+            assert_eq!(core.next_frame(-1), frames[step * 2]);
+        }
+        assert!(!rewind.step(&mut core));
+    }
+
+    #[test]
+    fn oldest_snapshots_are_dropped_past_the_budget() {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(fs::read("./tests/blargg/roms/2.gb").unwrap());
+
+        let mut rewind = Rewind::default();
+        rewind.configure(1, 4096);
+        for _ in 0..60 {
+            core.next_frame(-1);
+            rewind.frame_done(&mut core);
+        }
+        assert!(rewind.deltas_len <= 4096);
+        assert!(rewind.snapshots() < 60);
+
+        let oldest = 60 - rewind.snapshots() + 1;
+        while rewind.step(&mut core) {}
+        assert_eq!(core.frame_count as usize, oldest);
+    }
+}
@@ -0,0 +1,196 @@
+use serde_json::json;
+use crate::internal::memory::Memory;
+use crate::internal::state::StateWriter;
+
+// snapshots are laid out like Memory::save_ram: WRAM, HRAM then every SRAM bank
+const WRAM_LEN: usize = 0x2000;
+const HRAM_LEN: usize = 0x7F;
+const SRAM_BANK_LEN: usize = 0x2000;
+
+#[derive(Clone, Copy)]
+pub enum Comparison {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u16)
+}
+
+impl Comparison {
+    // 0 - unchanged | 1 - changed | 2 - increased | 3 - decreased | 4 - equal to `value`
+    pub fn from_u8(comparison: u8, value: u16) -> Option<Self> {
+        match comparison {
+            0 => Some(Comparison::Unchanged),
+            1 => Some(Comparison::Changed),
+            2 => Some(Comparison::Increased),
+            3 => Some(Comparison::Decreased),
+            4 => Some(Comparison::Value(value)),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SearchResult {
+    pub bank: Option<u8>, // SRAM bank, none for WRAM and HRAM
+    pub addr: u16,
+    pub value: u16,
+    pub previous: u16 // what the last filter compared against
+}
+
+// (SRAM bank, address) of an offset into a snapshot
+fn locate(offset: usize) -> (Option<u8>, u16) {
+    if offset < WRAM_LEN {
+        (None, 0xC000 + offset as u16)
+    } else if offset < WRAM_LEN + HRAM_LEN {
+        (None, 0xFF80 + (offset - WRAM_LEN) as u16)
+    } else {
+        let offset = offset - WRAM_LEN - HRAM_LEN;
+        (Some((offset / SRAM_BANK_LEN) as u8), 0xA000 + (offset % SRAM_BANK_LEN) as u16)
+    }
+}
+
+// narrows down where a value lives by comparing RAM between searches, the usual way of finding lives/HP for a GameShark code
+#[derive(Default)]
+pub struct RamSearch {
+    words: bool, // 16-bit little endian values instead of bytes
+    previous: Vec<u8>, // RAM at the last search
+    compared: Vec<u8>, // RAM the last filter compared against
+    candidates: Vec<u32> // snapshot offsets still matching every filter so far
+}
+
+impl RamSearch {
+    fn snapshot(memory: &Memory) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        memory.save_ram(&mut state);
+        state.buffer
+    }
+
+    fn value(&self, snapshot: &[u8], offset: usize) -> u16 {
+        if self.words {
+            u16::from_le_bytes([snapshot[offset], snapshot[offset + 1]])
+        } else {
+            snapshot[offset] as u16
+        }
+    }
+
+    // every address is a candidate again, words don't straddle WRAM/HRAM/SRAM banks
+    pub fn start(&mut self, memory: &Memory, words: bool) {
+        self.words = words;
+        self.previous = RamSearch::snapshot(memory);
+        self.compared = self.previous.clone();
+        self.candidates = (0..self.previous.len()).filter(|offset| {
+            !words || (offset + 1 < self.previous.len() && locate(*offset).0 == locate(offset + 1).0 && locate(offset + 1).1 == locate(*offset).1 + 1)
+        }).map(|offset| offset as u32).collect();
+    }
+
+    // keeps the candidates whose value passes `comparison` against the last search, returns how many are left
+    pub fn filter(&mut self, memory: &Memory, comparison: Comparison) -> usize {
+        let current = RamSearch::snapshot(memory);
+        if current.len() != self.previous.len() { // another cartridge got loaded
+            self.start(memory, self.words);
+            return self.candidates.len()
+        }
+
+        let candidates = std::mem::take(&mut self.candidates);
+        self.candidates = candidates.into_iter().filter(|offset| {
+            let value = self.value(&current, *offset as usize);
+            let previous = self.value(&self.previous, *offset as usize);
+            match comparison {
+                Comparison::Unchanged => value == previous,
+                Comparison::Changed => value != previous,
+                Comparison::Increased => value > previous,
+                Comparison::Decreased => value < previous,
+                Comparison::Value(expected) => value == expected
+            }
+        }).collect();
+        self.compared = std::mem::replace(&mut self.previous, current);
+        self.candidates.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // first `limit` candidates in address order (WRAM, HRAM then SRAM)
+    pub fn results(&self, memory: &Memory, limit: usize) -> Vec<SearchResult> {
+        let current = RamSearch::snapshot(memory);
+        self.candidates.iter().take(limit).map(|offset| {
+            let offset = *offset as usize;
+            let (bank, addr) = locate(offset);
+            let value = if offset < current.len() { self.value(&current, offset) } else { 0 };
+            SearchResult { bank, addr, value, previous: self.value(&self.compared, offset) }
+        }).collect()
+    }
+
+    // { count, results: [{ address, bank, value, previous }] }
+    pub fn to_json(&self, memory: &Memory, limit: usize) -> String {
+        let results: Vec<_> = self.results(memory, limit).iter().map(|result| {
+            json!({ "address": result.addr, "bank": result.bank, "value": result.value, "previous": result.previous })
+        }).collect();
+        json!({ "count": self.candidates.len(), "results": results }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_memory() -> Memory {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + battery
+        rom[0x149] = 0x03; // 4 banks of SRAM
+        let mut memory = Memory::default();
+        memory.load_cartridge(rom);
+        memory
+    }
+
+    #[test]
+    fn finds_a_decreasing_byte() {
+        let mut memory = setup_memory();
+        let mut search = RamSearch::default();
+        memory.write(0xC123, 3); // lives
+        memory.write(0xFF90, 3);
+        search.start(&memory, false);
+        assert_eq!(search.len(), WRAM_LEN + HRAM_LEN + 4 * SRAM_BANK_LEN);
+
+        memory.write(0xC123, 2);
+        memory.write(0xFF90, 4);
+        assert_eq!(search.filter(&memory, Comparison::Decreased), 1);
+        assert_eq!(search.results(&memory, 10), [SearchResult { bank: None, addr: 0xC123, value: 2, previous: 3 }]);
+
+        memory.write(0xC123, 1);
+        assert_eq!(search.filter(&memory, Comparison::Value(1)), 1);
+        assert_eq!(search.filter(&memory, Comparison::Changed), 0);
+        assert_eq!(search.to_json(&memory, 10), r#"{"count":0,"results":[]}"#);
+    }
+
+    #[test]
+    fn words_stay_inside_their_region() {
+        let mut memory = setup_memory();
+        let mut search = RamSearch::default();
+        search.start(&memory, true);
+        assert_eq!(search.len(), (WRAM_LEN - 1) + (HRAM_LEN - 1) + 4 * (SRAM_BANK_LEN - 1));
+
+        memory.write(0xFF80, 0x34);
+        memory.write(0xFF81, 0x12);
+        memory.sram[0x2000 * 2 + 0x10] = 0x01; // bank 2
+        assert_eq!(search.filter(&memory, Comparison::Increased), 4); // both bytes of the HRAM word, SRAM 0xA00F and 0xA010
+        let results = search.results(&memory, 10);
+        assert_eq!(results[0], SearchResult { bank: None, addr: 0xFF80, value: 0x1234, previous: 0 });
+        assert_eq!((results[2].bank, results[2].addr, results[2].value), (Some(2), 0xA00F, 0x0100));
+        assert_eq!(search.to_json(&memory, 1), r#"{"count":4,"results":[{"address":65408,"bank":null,"previous":0,"value":4660}]}"#);
+    }
+
+    #[test]
+    fn comparisons_from_the_frontend() {
+        assert!(matches!(Comparison::from_u8(2, 0), Some(Comparison::Increased)));
+        assert!(matches!(Comparison::from_u8(4, 0x1234), Some(Comparison::Value(0x1234))));
+        assert!(Comparison::from_u8(5, 0).is_none());
+        assert!(Comparison::from_u8(0xFF, 0).is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde_json::json;
+use crate::internal::bess::BessError;
+use crate::internal::core::component::CPU;
+
+pub const THUMBNAIL_WIDTH: usize = 160 / 2;
+pub const THUMBNAIL_HEIGHT: usize = 144 / 2;
+
+#[derive(Debug, PartialEq)]
+pub enum SlotError {
+    EmptySlot(String),
+    WrongRom { slot: u16, rom: u16 }, // global checksums
+    Bess(BessError)
+}
+
+impl From<BessError> for SlotError {
+    fn from(err: BessError) -> Self {
+        SlotError::Bess(err)
+    }
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlotError::EmptySlot(name) => write!(f, "slot {:?} is empty", name),
+            SlotError::WrongRom { slot, rom } => write!(f, "state belongs to another ROM (checksum 0x{:04X}, loaded ROM is 0x{:04X})", slot, rom),
+            SlotError::Bess(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+pub struct SaveSlot {
+    pub save_file: Vec<u8>, // BESS
+    pub thumbnail: Vec<u8>, // shades, every other pixel of every other line
+    thumbnail_sources: Vec<u8>, // palette of each thumbnail pixel
+    pub rom_checksum: u16,
+    pub frame: u64,
+    pub timestamp: f64 // supplied by the frontend, wasm has no clock
+}
+
+// named save states, they outlive the loaded cartridge so each one remembers which ROM it was made with
+#[derive(Default)]
+pub struct SaveSlots {
+    slots: BTreeMap<String, SaveSlot>
+}
+
+impl SaveSlots {
+    pub fn save(&mut self, name: &str, core: &mut CPU, timestamp: f64) {
+        let display = core.bus.get_display();
+        let sources = core.bus.get_display_sources();
+
+        let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        let mut thumbnail_sources = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        for y in 0..THUMBNAIL_HEIGHT {
+            for x in 0..THUMBNAIL_WIDTH {
+                let offset = (y * 2 * 160) + (x * 2);
+                thumbnail.push(display[offset]);
+                thumbnail_sources.push(sources[offset]);
+            }
+        }
+
+        self.slots.insert(String::from(name), SaveSlot {
+            save_file: core.create_save_file(),
+            thumbnail,
+            thumbnail_sources,
+            rom_checksum: core.bus.get_rom_checksum(),
+            frame: core.frame_count,
+            timestamp
+        });
+    }
+
+    pub fn load(&self, name: &str, core: &mut CPU) -> Result<(), SlotError> {
+        let slot = self.slots.get(name).ok_or_else(|| SlotError::EmptySlot(String::from(name)))?;
+
+        let rom = core.bus.get_rom_checksum();
+        if slot.rom_checksum != rom {
+            return Err(SlotError::WrongRom { slot: slot.rom_checksum, rom })
+        }
+        Ok(core.load_save_file(slot.save_file.clone())?)
+    }
+
+    pub fn delete(&mut self, name: &str) -> bool {
+        self.slots.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SaveSlot> {
+        self.slots.get(name)
+    }
+
+    // thumbnail as RGBA in the current palette
+    pub fn thumbnail(&self, name: &str, core: &CPU) -> Option<Vec<u8>> {
+        self.slots.get(name).map(|slot| core.bus.to_rgba(&slot.thumbnail, &slot.thumbnail_sources))
+    }
+
+    // [{ name, romChecksum, frame, timestamp }] sorted by name
+    pub fn to_json(&self) -> String {
+        let slots: Vec<_> = self.slots.iter().map(|(name, slot)| {
+            json!({ "name": name, "romChecksum": slot.rom_checksum, "frame": slot.frame, "timestamp": slot.timestamp })
+        }).collect();
+        json!(slots).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::internal::bess;
+
+    fn setup_core(rom: Vec<u8>) -> CPU {
+        let mut core = CPU::default();
+        core.initialize_core();
+        core.bus.load_cartridge(rom);
+        core
+    }
+
+    #[test]
+    fn slots_restore_their_own_state() {
+        let mut core = setup_core(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        let mut slots = SaveSlots::default();
+
+        for _ in 0..5 {
+            core.next_frame(-1);
+        }
+        slots.save("early", &mut core, 1000.0);
+        let early_pc = core.pc;
+        for _ in 0..30 {
+            core.next_frame(-1);
+        }
+        slots.save("late", &mut core, 2000.0);
+        let late_display = core.next_frame(-1);
+
+        slots.load("early", &mut core).unwrap();
+        assert_eq!(core.pc, early_pc);
+        assert_eq!(core.frame_count, 5);
+
+        slots.load("late", &mut core).unwrap();
+        assert_eq!(core.frame_count, 35);
+        assert_eq!(core.next_frame(-1), late_display);
+
+        let slot = slots.get("late").unwrap();
+        assert_eq!(slot.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        assert_eq!(slot.timestamp, 2000.0);
+        assert_eq!(slots.thumbnail("late", &core).unwrap().len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+        assert_eq!(slots.to_json(), format!(r#"[{{"frame":5,"name":"early","romChecksum":{0},"timestamp":1000.0}},{{"frame":35,"name":"late","romChecksum":{0},"timestamp":2000.0}}]"#, slot.rom_checksum));
+    }
+
+    #[test]
+    fn state_from_another_rom_is_refused() {
+        let rom = fs::read("./tests/blargg/roms/2.gb").unwrap();
+        let mut other_rom = rom.clone();
+        other_rom[0x14F] ^= 0xFF;
+
+        let mut slots = SaveSlots::default();
+        let mut core = setup_core(rom.clone());
+        core.next_frame(-1);
+        slots.save("1", &mut core, 0.0);
+
+        let mut other = setup_core(other_rom);
+        let pc = other.pc;
+        assert_eq!(slots.load("1", &mut other), Err(SlotError::WrongRom { slot: slots.get("1").unwrap().rom_checksum, rom: other.bus.get_rom_checksum() }));
+        assert_eq!(other.pc, pc);
+
+        assert_eq!(slots.load("2", &mut core), Err(SlotError::EmptySlot(String::from("2"))));
+        assert!(slots.delete("1"));
+        assert!(slots.get("1").is_none());
+    }
+
+    #[test]
+    fn corrupted_slot_leaves_the_game_running() {
+        let mut core = setup_core(fs::read("./tests/blargg/roms/2.gb").unwrap());
+        let mut slots = SaveSlots::default();
+        core.next_frame(-1);
+        slots.save("1", &mut core, 0.0);
+        core.next_frame(-1);
+
+        let save_file = &mut slots.slots.get_mut("1").unwrap().save_file;
+        let block = bess::parse(save_file).unwrap().into_iter().find(|block| block.name == *b"EMUF").unwrap();
+        let offset = block.data.as_ptr() as usize - save_file.as_ptr() as usize;
+        save_file[offset + 2 + 13] = 0xFF; // EI delay, after the version, registers, PC, SP and IME
+
+        let hash = core.state_hash();
+        assert_eq!(slots.load("1", &mut core), Err(SlotError::Bess(BessError::InvalidState("EI delay"))));
+        assert_eq!(core.state_hash(), hash);
+    }
+}
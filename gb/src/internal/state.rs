@@ -0,0 +1,171 @@
+use crate::internal::bess::BessError;
+
+// private save state block stored next to the standard BESS blocks, holds everything BESS has no room for
+// (mid-instruction CPU state, PPU fetcher/FIFOs, timer internals) so a state resumes on the exact same cycle
+pub const STATE_BLOCK: &str = "EMUF";
+pub const STATE_VERSION: u16 = 2;
+
+// brings a block written by an older version up to date, one version at a time
+// when the layout changes bump STATE_VERSION and add an arm converting the previous version's payload
+pub fn migrate(version: u16, payload: Vec<u8>) -> Result<Vec<u8>, BessError> {
+    match version {
+        1 => { // frame counter added at the end
+            let mut payload = payload;
+            payload.extend_from_slice(&0u64.to_le_bytes());
+            migrate(2, payload)
+        },
+        STATE_VERSION => Ok(payload),
+        _ => Err(BessError::UnsupportedStateVersion(version))
+    }
+}
+
+pub fn encode_block(payload: Vec<u8>) -> Vec<u8> {
+    let mut block = STATE_VERSION.to_le_bytes().to_vec();
+    block.extend(payload);
+    block
+}
+
+pub fn decode_block(block: &[u8]) -> Result<Vec<u8>, BessError> {
+    if block.len() < 2 {
+        return Err(BessError::BlockOutOfBounds(*b"EMUF"))
+    }
+    migrate(u16::from_le_bytes([block[0], block[1]]), block[2..].to_vec())
+}
+
+// all values are little endian
+#[derive(Default)]
+pub struct StateWriter {
+    pub buffer: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn write_u8(&mut self, val: u8) {
+        self.buffer.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.buffer.push(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.buffer.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.buffer.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.buffer.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, val: usize) { // stored as u32 so wasm and native states are interchangeable
+        self.write_u32(val as u32);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    buffer: &'a [u8],
+    ptr: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, ptr: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], BessError> {
+        if len > self.buffer.len() - self.ptr {
+            return Err(BessError::StateTruncated)
+        }
+        let bytes = &self.buffer[self.ptr..self.ptr + len];
+        self.ptr += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, BessError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, BessError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, BessError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, BessError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, BessError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, BessError> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    // usize that has to be at most `max`, keeps a corrupted state from indexing out of bounds later on
+    pub fn read_index(&mut self, max: usize, field: &'static str) -> Result<usize, BessError> {
+        let val = self.read_usize()?;
+        check(val <= max, field)?;
+        Ok(val)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ptr == self.buffer.len()
+    }
+}
+
+// states written by this emulator always pass, a failure means the block is corrupted
+pub fn check(valid: bool, field: &'static str) -> Result<(), BessError> {
+    if valid { Ok(()) } else { Err(BessError::InvalidState(field)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let mut writer = StateWriter::default();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_usize(0x789ABC);
+        writer.write_bytes(&[1, 2, 3]);
+
+        let block = encode_block(writer.buffer);
+        assert_eq!(block[0..2], STATE_VERSION.to_le_bytes());
+
+        let payload = decode_block(&block).unwrap();
+        let mut reader = StateReader::new(&payload);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_index(0x789ABB, "value"), Err(BessError::InvalidState("value")));
+        assert_eq!(reader.read_bytes(3), Ok(&[1, 2, 3][..]));
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), Err(BessError::StateTruncated));
+    }
+
+    #[test]
+    fn version_1_gets_a_frame_counter() {
+        let mut block = 1u16.to_le_bytes().to_vec();
+        block.extend_from_slice(&[0xAB, 0xCD]);
+        assert_eq!(decode_block(&block).unwrap(), [0xAB, 0xCD, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        assert_eq!(decode_block(&(STATE_VERSION + 1).to_le_bytes()), Err(BessError::UnsupportedStateVersion(STATE_VERSION + 1)));
+    }
+}
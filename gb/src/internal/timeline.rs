@@ -0,0 +1,230 @@
+use crate::internal::ppu::Mode;
+use serde_json::{json, Value};
+
+const T_CYCLES_PER_MICROSECOND: f64 = 4.194304;
+
+// chrome trace "threads" each event is drawn on
+const CPU_TRACK: u8 = 0;
+const PPU_TRACK: u8 = 1;
+const TIMER_TRACK: u8 = 2;
+const INTERRUPT_TRACK: u8 = 3;
+const DMA_TRACK: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventKind {
+    InterruptRequested(u8), // bit in IF
+    InterruptServiced(u8), // bit in IF
+    RegisterWrite(u16, u8),
+    OamDma(u8), // source page
+    TimerOverflow,
+    HaltEntered,
+    HaltExited,
+    ModeChanged(Mode)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    pub cycle: u32, // T-cycles since the start of the frame
+    pub ly: u8,
+    pub dot: u16, // position within the scanline
+    pub kind: EventKind
+}
+
+pub struct Timeline {
+    pub enabled: bool,
+    pub events: Vec<Event>,
+    pub cycle: u32
+}
+
+impl Timeline {
+    pub fn begin_frame(&mut self) {
+        self.events.clear();
+        self.cycle = 0;
+    }
+
+    pub fn record(&mut self, ly: u8, dot: u16, kind: EventKind) {
+        if self.enabled {
+            self.events.push(Event { cycle: self.cycle, ly, dot, kind });
+        }
+    }
+
+    fn interrupt_name(bit: u8) -> &'static str {
+        match bit {
+            0 => "VBLANK",
+            1 => "STAT",
+            2 => "TIMER",
+            3 => "SERIAL",
+            4 => "JOYPAD",
+            _ => "UNKNOWN"
+        }
+    }
+
+    fn register_name(addr: u16) -> String {
+        match addr {
+            0xFF40 => String::from("LCDC"),
+            0xFF41 => String::from("STAT"),
+            0xFF42 => String::from("SCY"),
+            0xFF43 => String::from("SCX"),
+            0xFF45 => String::from("LYC"),
+            0xFF4A => String::from("WY"),
+            0xFF4B => String::from("WX"),
+            _ => format!("0x{:04X}", addr)
+        }
+    }
+
+    fn timestamp(cycle: u32) -> f64 {
+        cycle as f64 / T_CYCLES_PER_MICROSECOND
+    }
+
+    fn instant(name: String, track: u8, event: &Event, mut args: Value) -> Value {
+        args["ly"] = json!(event.ly);
+        args["dot"] = json!(event.dot);
+        json!({ "name": name, "ph": "i", "s": "t", "pid": 0, "tid": track, "ts": Timeline::timestamp(event.cycle), "args": args })
+    }
+
+    // exports the recorded frame in the chrome trace-event format (chrome://tracing, perfetto)
+    pub fn to_chrome_trace(&self) -> String {
+        let mut trace = vec![];
+
+        for (track, name) in [(CPU_TRACK, "CPU"), (PPU_TRACK, "PPU"), (TIMER_TRACK, "Timer"), (INTERRUPT_TRACK, "Interrupts"), (DMA_TRACK, "DMA")] {
+            trace.push(json!({ "name": "thread_name", "ph": "M", "pid": 0, "tid": track, "args": { "name": name } }));
+        }
+
+        // modes are drawn as spans lasting until the next mode change
+        let mut current_mode: Option<&Event> = None;
+
+        for event in &self.events {
+            match event.kind {
+                EventKind::ModeChanged(_) => {
+                    if let Some(start) = current_mode {
+                        if let EventKind::ModeChanged(mode) = start.kind {
+                            trace.push(json!({ "name": format!("{:?}", mode), "ph": "X", "pid": 0, "tid": PPU_TRACK, "ts": Timeline::timestamp(start.cycle),
+                                "dur": Timeline::timestamp(event.cycle - start.cycle), "args": { "ly": start.ly, "dot": start.dot } }));
+                        }
+                    }
+                    current_mode = Some(event);
+                },
+                EventKind::InterruptRequested(bit) => trace.push(Timeline::instant(format!("{} requested", Timeline::interrupt_name(bit)), INTERRUPT_TRACK, event, json!({}))),
+                EventKind::InterruptServiced(bit) => trace.push(Timeline::instant(format!("{} serviced", Timeline::interrupt_name(bit)), CPU_TRACK, event, json!({}))),
+                EventKind::RegisterWrite(addr, val) => trace.push(Timeline::instant(format!("{} write", Timeline::register_name(addr)), PPU_TRACK, event, json!({ "value": format!("0x{:02X}", val) }))),
+                EventKind::OamDma(page) => trace.push(Timeline::instant(String::from("OAM DMA"), DMA_TRACK, event, json!({ "source": format!("0x{:02X}00", page) }))),
+                EventKind::TimerOverflow => trace.push(Timeline::instant(String::from("TIMA overflow"), TIMER_TRACK, event, json!({}))),
+                EventKind::HaltEntered => trace.push(Timeline::instant(String::from("HALT entered"), CPU_TRACK, event, json!({}))),
+                EventKind::HaltExited => trace.push(Timeline::instant(String::from("HALT exited"), CPU_TRACK, event, json!({})))
+            }
+        }
+
+        if let Some(start) = current_mode {
+            if let EventKind::ModeChanged(mode) = start.kind {
+                trace.push(json!({ "name": format!("{:?}", mode), "ph": "X", "pid": 0, "tid": PPU_TRACK, "ts": Timeline::timestamp(start.cycle),
+                    "dur": Timeline::timestamp(self.cycle - start.cycle), "args": { "ly": start.ly, "dot": start.dot } }));
+            }
+        }
+
+        json!({ "traceEvents": trace, "displayTimeUnit": "ns" }).to_string()
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            events: vec![],
+            cycle: 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::memory::Memory;
+
+    fn count(memory: &Memory, kind: EventKind) -> usize {
+        memory.timeline.events.iter().filter(|event| event.kind == kind).count()
+    }
+
+    // LCD off, 16 cycle timer period and TIMA reloaded with 0xF0 so it overflows every 64 M-cycles
+    fn setup_memory() -> Memory {
+        let mut memory = Memory::default();
+        memory.set_timeline_enabled(true);
+        memory.write(0xFF06, 0xF0);
+        memory.write(0xFF05, 0xF0);
+        memory.write(0xFF07, 0b101);
+        memory
+    }
+
+    #[test]
+    fn timer_overflow_is_recorded_once_per_reload() {
+        let mut memory = setup_memory();
+        for _ in 0..(64 * 10) + 32 {
+            memory.update_components();
+        }
+
+        assert_eq!(count(&memory, EventKind::TimerOverflow), 10);
+        assert_eq!(count(&memory, EventKind::InterruptRequested(2)), 10);
+        let cycles: Vec<_> = memory.timeline.events.iter().filter(|event| event.kind == EventKind::TimerOverflow).map(|event| event.cycle).collect();
+        assert!(cycles.windows(2).all(|pair| pair[1] - pair[0] == 64 * 4));
+    }
+
+    #[test]
+    fn pending_timer_irq_is_not_an_overflow() {
+        let mut memory = setup_memory();
+        memory.timer.tima_irq = true; // requested but not yet moved into IF, e.g. right after loading a state
+        memory.update_components();
+
+        assert_eq!(count(&memory, EventKind::TimerOverflow), 0);
+        assert_eq!(count(&memory, EventKind::InterruptRequested(2)), 1);
+    }
+
+    #[test]
+    fn nothing_is_recorded_while_disabled() {
+        let mut memory = setup_memory();
+        memory.set_timeline_enabled(false);
+        for _ in 0..(64 * 10) {
+            memory.update_components();
+        }
+
+        assert!(memory.timeline.events.is_empty());
+    }
+
+    #[test]
+    fn chrome_trace_has_spans_and_instants() {
+        let mut timeline = Timeline { enabled: true, ..Timeline::default() };
+        timeline.record(0, 0, EventKind::ModeChanged(Mode::OAMSCAN));
+        timeline.cycle = 80;
+        timeline.record(0, 80, EventKind::ModeChanged(Mode::DRAW));
+        timeline.cycle = 100;
+        timeline.record(0, 100, EventKind::TimerOverflow);
+        timeline.cycle = 120;
+        timeline.record(0, 120, EventKind::RegisterWrite(0xFF40, 0x91));
+        timeline.cycle = 456;
+
+        let trace: Value = serde_json::from_str(&timeline.to_chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(trace["displayTimeUnit"], "ns");
+        assert_eq!(events.len(), 5 + 4); // one name per track, then the frame
+        assert_eq!(events[0], json!({ "name": "thread_name", "ph": "M", "pid": 0, "tid": CPU_TRACK, "args": { "name": "CPU" } }));
+        assert_eq!(events[4]["args"]["name"], "DMA");
+
+        // the mode spans are pushed when the next mode starts, so they come after the events that happen during them
+        assert_eq!(events[5], json!({ "name": "OAMSCAN", "ph": "X", "pid": 0, "tid": PPU_TRACK, "ts": 0.0, "dur": 80.0 / T_CYCLES_PER_MICROSECOND, "args": { "ly": 0, "dot": 0 } }));
+        assert_eq!(events[6], json!({ "name": "TIMA overflow", "ph": "i", "s": "t", "pid": 0, "tid": TIMER_TRACK, "ts": 100.0 / T_CYCLES_PER_MICROSECOND, "args": { "ly": 0, "dot": 100 } }));
+        assert_eq!(events[7], json!({ "name": "LCDC write", "ph": "i", "s": "t", "pid": 0, "tid": PPU_TRACK, "ts": 120.0 / T_CYCLES_PER_MICROSECOND, "args": { "ly": 0, "dot": 120, "value": "0x91" } }));
+        assert_eq!(events[8]["name"], "DRAW"); // the last mode lasts until the end of the recording
+        assert_eq!(events[8]["dur"], json!(376.0 / T_CYCLES_PER_MICROSECOND));
+    }
+
+    #[test]
+    fn begin_frame_clears_the_recording() {
+        let mut timeline = Timeline { enabled: true, ..Timeline::default() };
+        timeline.cycle = 100;
+        timeline.record(0, 100, EventKind::HaltEntered);
+        timeline.begin_frame();
+
+        assert!(timeline.events.is_empty());
+        assert_eq!(timeline.cycle, 0);
+        let trace: Value = serde_json::from_str(&timeline.to_chrome_trace()).unwrap();
+        assert_eq!(trace["traceEvents"].as_array().unwrap().len(), 5); // only the track names
+    }
+}
@@ -1,13 +1,15 @@
+use crate::internal::bess::BessError;
+use crate::internal::state::{StateReader, StateWriter};
+
 pub struct Timer {
-    pub tima_irq: usize, // set if IRQ should be dispatched
+    pub tima_irq: bool, // set if IRQ should be dispatched
 
     pub sysclock: u16,
-    sysclock_cycles: usize,
     tma: u8,
-    tma_previous: Option<u8>, // used for writes and TIMA overflows in the same cycle
     tima: u8,
     tac: u8,
-    current_freq: u16 // sysclock frequency specified by TAC
+    overflowed: bool, // TIMA wrapped last cycle and reads 0x00 until it gets reloaded this cycle
+    reloading: bool // TMA was loaded into TIMA this cycle, TIMA writes are ignored and TMA writes go through to TIMA
 }
 
 impl Timer {
@@ -16,64 +18,288 @@ impl Timer {
             0xFF04 => (self.sysclock >> 8) as u8, // div is top 8 bits of sysclock
             0xFF05 => self.tima,
             0xFF06 => self.tma,
-            0xFF07 => self.tac,
+            0xFF07 => self.tac | 0xF8, // unused bits read 1
             _ => panic!("recieved invalid address")
         }
     }
 
     pub fn write_registers(&mut self, addr: u16, val: u8) {
         match addr {
-            0xFF04 => self.sysclock = 0x0,
-            0xFF05 => self.tima = val,
+            0xFF04 => self.set_sysclock(0x0),
+            0xFF05 => if !self.reloading {
+                self.tima = val;
+                self.overflowed = false; // cancels the reload and the interrupt
+            },
             0xFF06 => {
-                self.tma_previous.get_or_insert(self.tma);
                 self.tma = val;
+                if self.reloading {
+                    self.tima = val;
+                }
+            },
+            0xFF07 => {
+                let was_high = self.timer_input();
+                self.tac = val & 0x7;
+                if was_high && !self.timer_input() { // DMG increments even when the timer is being disabled
+                    self.increment_tima();
+                }
             },
-            0xFF07 => self.tac = val,
             _ => panic!("recieved invalid address")
         };
     }
 
+    fn input_bit(&self) -> u16 {
+        match self.tac & 0x3 {
+            0 => 9, // 1024 (default)
+            1 => 3, // 16
+            2 => 5, // 64
+            3 => 7, // 256
+            _ => unreachable!()
+        }
+    }
+
+    // TIMA is clocked by the falling edge of the TAC enable bit AND'd with the selected sysclock bit
+    fn timer_input(&self) -> bool {
+        (self.tac >> 2) & 0x1 == 1 && (self.sysclock >> self.input_bit()) & 0x1 == 1
+    }
+
+    fn set_sysclock(&mut self, sysclock: u16) {
+        let was_high = self.timer_input();
+        self.sysclock = sysclock;
+        if was_high && !self.timer_input() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (result, overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+        self.overflowed = overflow;
+    }
+
     pub fn update(&mut self) {
-        self.sysclock += 4;
-
-        if (self.tac >> 2 & 0x1) == 1 {
-            let bit_set_prev = self.current_freq;
-
-            self.current_freq = match self.tac & 0x3 {
-                0 => (self.sysclock >> 9) & 0x1, // 1024 (default)
-                1 => (self.sysclock >> 3) & 0x1, // 16
-                2 => (self.sysclock >> 5) & 0x1, // 64
-                3 => (self.sysclock >> 7) & 0x1, // 256
-                _ => unreachable!()
-            };
-
-            if bit_set_prev == 1 && self.current_freq == 0 {
-                let result = self.tima.overflowing_add(1);
-                if result.1 {
-                    self.tima = if self.tma_previous.is_none() { self.tma } else { self.tma_previous.unwrap() };
-                    self.tima_irq = 2;
-                } else {
-                    self.tima = result.0;
-                }
+        self.reloading = false;
+        if self.overflowed { // reload and interrupt are delayed by 1 cycle
+            self.overflowed = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            self.tima_irq = true;
+        }
+
+        self.set_sysclock(self.sysclock.wrapping_add(4)); // DIV wraps around
+    }
+
+    pub fn is_reloading(&self) -> bool { // TMA was loaded into TIMA by the last update
+        self.reloading
+    }
+
+    // M-cycles until TIMA overflows (or gets reloaded)
+    pub fn cycles_until_event(&self) -> usize {
+        if self.overflowed || self.reloading {
+            return 1
+        }
+        if (self.tac >> 2) & 0x1 == 0 {
+            return usize::MAX
+        }
+
+        let period = 1usize << (self.input_bit() + 1); // T-cycles between falling edges
+        let first_edge = period - (self.sysclock as usize % period);
+        (first_edge + ((0xFF - self.tima as usize) * period)) / 4
+    }
+
+    // same as calling update() `cycles` times
+    pub fn run(&mut self, mut cycles: usize) {
+        while cycles > 0 {
+            let idle = (self.cycles_until_event() - 1).min(cycles); // TIMA can't overflow within these
+            if idle == 0 {
+                self.update();
+                cycles -= 1;
+                continue
+            }
+
+            let start = self.sysclock as usize;
+            let end = start + (idle * 4);
+            if (self.tac >> 2) & 0x1 == 1 {
+                let period_bits = self.input_bit() + 1;
+                self.tima += ((end >> period_bits) - (start >> period_bits)) as u8;
             }
+            self.sysclock = end as u16; // DIV wraps around
+            cycles -= idle;
         }
+    }
 
-        self.tma_previous = None;
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.sysclock);
+        state.write_u8(self.tima);
+        state.write_u8(self.tma);
+        state.write_u8(self.tac);
+        state.write_bool(self.overflowed);
+        state.write_bool(self.reloading);
+        state.write_bool(self.tima_irq);
+    }
+
+    pub fn load_state(state: &mut StateReader) -> Result<Timer, BessError> {
+        Ok(Timer {
+            sysclock: state.read_u16()?,
+            tima: state.read_u8()?,
+            tma: state.read_u8()?,
+            tac: state.read_u8()? & 0x7,
+            overflowed: state.read_bool()?,
+            reloading: state.read_bool()?,
+            tima_irq: state.read_bool()?
+        })
     }
 }
 
 impl Default for Timer {
     fn default() -> Self {
-        Self { 
+        Self {
             sysclock: 0x0,
             tma: 0,
-            tma_previous: None,
             tima: 0x00,
             tac: 0x0,
-            tima_irq: 0,
-            sysclock_cycles: 0,
-            current_freq: 0
+            tima_irq: false,
+            overflowed: false,
+            reloading: false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 cycle period, TIMA is about to overflow
+    fn setup_timer() -> Timer {
+        let mut timer = Timer::default();
+        timer.write_registers(0xFF07, 0b101);
+        timer.write_registers(0xFF05, 0xFF);
+        timer.write_registers(0xFF06, 0x42);
+        timer
+    }
+
+    fn update_until_overflow(timer: &mut Timer) {
+        while !timer.overflowed {
+            timer.update();
+        }
+    }
+
+    #[test]
+    fn div_write_increments_tima_on_falling_edge() {
+        let mut timer = Timer::default();
+        timer.write_registers(0xFF07, 0b101);
+        timer.update();
+        timer.update(); // bit 3 set
+        timer.write_registers(0xFF04, 0x00);
+        assert_eq!(timer.read_registers(0xFF05), 1);
+
+        timer.update(); // bit 3 clear
+        timer.write_registers(0xFF04, 0x00);
+        assert_eq!(timer.read_registers(0xFF05), 1);
+    }
+
+    #[test]
+    fn disabling_timer_increments_tima_on_falling_edge() {
+        let mut timer = Timer::default();
+        timer.write_registers(0xFF07, 0b101);
+        timer.update();
+        timer.update();
+        timer.write_registers(0xFF07, 0b001);
+        assert_eq!(timer.read_registers(0xFF05), 1);
+
+        timer.write_registers(0xFF07, 0b101); // bit 3 is still set so toggling keeps clocking TIMA
+        timer.write_registers(0xFF07, 0b001);
+        assert_eq!(timer.read_registers(0xFF05), 2);
+
+        timer.update();
+        timer.update(); // bit 3 clear
+        timer.write_registers(0xFF07, 0b101);
+        timer.write_registers(0xFF07, 0b001);
+        assert_eq!(timer.read_registers(0xFF05), 2);
+    }
+
+    #[test]
+    fn changing_frequency_increments_tima_on_falling_edge() {
+        let mut timer = Timer::default();
+        timer.write_registers(0xFF07, 0b101);
+        timer.update();
+        timer.update(); // sysclock = 8, bit 3 set and bit 5 clear
+        timer.write_registers(0xFF07, 0b110);
+        assert_eq!(timer.read_registers(0xFF05), 1);
+    }
+
+    #[test]
+    fn tima_reload_is_delayed_by_one_cycle() {
+        let mut timer = setup_timer();
+        update_until_overflow(&mut timer);
+        assert_eq!(timer.read_registers(0xFF05), 0x00);
+        assert!(!timer.tima_irq);
+
+        timer.update();
+        assert_eq!(timer.read_registers(0xFF05), 0x42);
+        assert!(timer.tima_irq);
+    }
+
+    #[test]
+    fn tima_write_before_reload_cancels_it() {
+        let mut timer = setup_timer();
+        update_until_overflow(&mut timer);
+        timer.write_registers(0xFF05, 0x10);
+
+        timer.update();
+        assert_eq!(timer.read_registers(0xFF05), 0x10);
+        assert!(!timer.tima_irq);
+    }
+
+    #[test]
+    fn tima_write_during_reload_is_ignored() {
+        let mut timer = setup_timer();
+        update_until_overflow(&mut timer);
+        timer.update();
+        timer.write_registers(0xFF05, 0x10);
+        assert_eq!(timer.read_registers(0xFF05), 0x42);
+    }
+
+    #[test]
+    fn tma_write_during_reload_goes_to_tima() {
+        let mut timer = setup_timer();
+        update_until_overflow(&mut timer);
+        timer.update();
+        timer.write_registers(0xFF06, 0x55);
+        assert_eq!(timer.read_registers(0xFF05), 0x55);
+
+        timer.update(); // the window is only 1 cycle long
+        timer.write_registers(0xFF06, 0x66);
+        assert_eq!(timer.read_registers(0xFF05), 0x55);
+    }
+
+    #[test]
+    fn run_matches_updating_every_cycle() {
+        for tac in [0b000, 0b100, 0b101, 0b110, 0b111] {
+            for tima in [0x00, 0xF0, 0xFF] {
+                let mut stepped = Timer::default();
+                let mut skipped = Timer::default();
+                for timer in [&mut stepped, &mut skipped] {
+                    timer.write_registers(0xFF07, tac);
+                    timer.write_registers(0xFF05, tima);
+                    timer.write_registers(0xFF06, 0xFE);
+                }
+
+                let mut irqs = (0, 0);
+                for chunk in [1, 3, 17, 250, 1000, 4096, 7] {
+                    for _ in 0..chunk {
+                        stepped.update();
+                        irqs.0 += stepped.tima_irq as usize;
+                        stepped.tima_irq = false;
+                    }
+                    skipped.run(chunk);
+                    irqs.1 += skipped.tima_irq as usize;
+                    skipped.tima_irq = false;
+
+                    assert_eq!(skipped.sysclock, stepped.sysclock);
+                    assert_eq!(skipped.read_registers(0xFF05), stepped.read_registers(0xFF05));
+                }
+                assert_eq!(irqs.1 > 0, irqs.0 > 0);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,11 @@
 use wasm_bindgen::prelude::*;
 use crate::internal::core::component::CPU;
+use crate::internal::slots::SaveSlots;
+use crate::internal::rewind::Rewind;
+use crate::internal::movie::{Movie, MovieSession};
+use crate::internal::search::{Comparison, RamSearch};
+use crate::internal::patch::apply_patch;
+use crate::internal::archive::{check_rom, extract_rom};
 extern crate console_error_panic_hook;
 use std::panic;
 
@@ -36,7 +42,29 @@ pub fn u32_to_little_endian(val: u32) -> [u8; 4] {
 
 #[wasm_bindgen]
 struct Emulator {
-    core: CPU
+    core: CPU,
+    slots: SaveSlots, // kept when another cartridge is loaded
+    rewind: Rewind,
+    movie: MovieSession,
+    search: RamSearch
+}
+
+impl Emulator {
+    fn load_rom(&mut self, rom: Vec<u8>) {
+        self.core = CPU::default();
+        self.core.initialize_core();
+        self.core.bus.load_cartridge(rom);
+        self.rewind.clear();
+        self.movie.stop();
+        self.search = RamSearch::default();
+    }
+
+    fn start_movie(&mut self, movie: Movie) -> Result<(), String> {
+        self.movie.play(&mut self.core, movie).map_err(|err| err.to_string())?;
+        self.rewind.clear();
+        self.core.bus.update_framebuffer();
+        Ok(())
+    }
 }
 
 #[wasm_bindgen]
@@ -44,29 +72,251 @@ impl Emulator {
     pub fn new() -> Emulator {   
         console_error_panic_hook::set_once();
         Emulator {
-            core: CPU::default()
+            core: CPU::default(),
+            slots: SaveSlots::default(),
+            rewind: Rewind::default(),
+            movie: MovieSession::default(),
+            search: RamSearch::default()
         }
     }
 
-    pub fn load_catridge(&mut self, bytes: Vec<u8>) {
-        self.core = CPU::default();
-        self.core.initialize_core();
-        self.core.bus.load_cartridge(bytes);
+    // .gb/.gbc files, or a zip or gzip file with one in it
+    pub fn load_catridge(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        let rom = extract_rom(bytes).map_err(|err| err.to_string())?;
+        self.load_rom(rom);
+        Ok(())
+    }
+
+    // IPS, UPS or BPS patch applied to the ROM before it's loaded, nothing changes if the patch doesn't fit the ROM
+    pub fn load_patched_catridge(&mut self, rom: Vec<u8>, patch: Vec<u8>) -> Result<(), String> {
+        let rom = extract_rom(rom).map_err(|err| err.to_string())?;
+        let rom = apply_patch(&rom, &patch).map_err(|err| err.to_string())?;
+        check_rom(&rom).map_err(|err| err.to_string())?; // the patch can change the header
+        self.load_rom(rom);
+        Ok(())
     }
 
+    // one byte per pixel with the raw 0-3 shades unless another color format was picked
     pub fn render(&mut self, keypress: i8) -> Vec<u8> {
-        self.core.next_frame(keypress).to_vec()
+        self.run_frame(keypress);
+        self.core.bus.get_framebuffer().to_vec()
+    }
+
+    // same as render but the frame is left in wasm memory, see framebuffer_ptr
+    pub fn run_frame(&mut self, keypress: i8) {
+        self.movie.next_frame(&mut self.core, keypress);
+        self.core.bus.update_framebuffer();
+        self.rewind.frame_done(&mut self.core);
+    }
+
+    // snapshot every `interval` frames (0 turns rewinding off) keeping up to `budget_kb` of history
+    pub fn set_rewind(&mut self, interval: usize, budget_kb: usize) {
+        self.rewind.configure(interval, budget_kb.saturating_mul(1024)); // usize is 32 bits on wasm
+    }
+
+    // goes back one snapshot and shows its frame, false once there's no history left
+    pub fn rewind_step(&mut self) -> bool {
+        let rewound = self.rewind.step(&mut self.core);
+        self.core.bus.update_framebuffer();
+        rewound
+    }
+
+    // records the input of every frame from power-on, or from the current state which gets embedded in the movie
+    // the display is hashed every `sync_interval` frames (0 for never) so replays can tell when they desync
+    pub fn record_movie(&mut self, from_save_state: bool, sync_interval: u32) {
+        self.movie.record(&mut self.core, from_save_state, sync_interval);
+        self.rewind.clear();
+        self.core.bus.update_framebuffer();
+    }
+
+    // the input passed to run_frame is ignored until the movie runs out
+    pub fn play_movie(&mut self, file: Vec<u8>) -> Result<(), String> {
+        let movie = Movie::from_bytes(&file).map_err(|err| err.to_string())?;
+        self.start_movie(movie)
+    }
+
+    // BK2 style input log, see Movie::to_text
+    pub fn play_movie_text(&mut self, text: &str) -> Result<(), String> {
+        let movie = Movie::from_text(text).map_err(|err| err.to_string())?;
+        self.start_movie(movie)
+    }
+
+    pub fn stop_movie(&mut self) {
+        self.movie.stop();
+    }
+
+    pub fn movie_file(&self) -> Vec<u8> {
+        self.movie.movie().to_bytes()
+    }
+
+    pub fn movie_text(&self) -> String {
+        self.movie.movie().to_text()
+    }
+
+    // { mode: "idle" | "recording" | "playing", frame, length, desync: first mismatching frame or null }
+    pub fn movie_status(&self) -> String {
+        self.movie.to_json(&self.core)
+    }
+
+    // same inputs from the same state give the same hash on every platform, a BigInt on the JS side
+    pub fn state_hash(&mut self) -> u64 {
+        self.core.state_hash()
+    }
+
+    // Game Genie (ABC-DEF-GHI or ABC-DEF) or GameShark (01VVAAAA, 8XVVAAAA for SRAM bank X) codes
+    // they're dropped when another cartridge is loaded
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), String> {
+        self.core.bus.cheats.add(code).map_err(|err| err.to_string())
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) -> bool {
+        self.core.bus.cheats.remove(code)
+    }
+
+    pub fn set_cheat_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        self.core.bus.cheats.set_enabled(code, enabled)
+    }
+
+    // [{ code, kind: "gamegenie" | "gameshark", enabled }]
+    pub fn list_cheats(&self) -> String {
+        self.core.bus.cheats.to_json()
+    }
+
+    // RAM search over WRAM, HRAM and every SRAM bank: start, play a bit, filter, repeat until few candidates are left
+    // width is 1 or 2 bytes (little endian)
+    pub fn ram_search_start(&mut self, width: u8) {
+        self.search.start(&self.core.bus, width == 2);
+    }
+
+    // comparison: 0 - unchanged | 1 - changed | 2 - increased | 3 - decreased | 4 - equal to `value`
+    // compared against the RAM at the last search, returns how many candidates are left
+    pub fn ram_search_filter(&mut self, comparison: u8, value: u16) -> Result<usize, String> {
+        let comparison = Comparison::from_u8(comparison, value).ok_or_else(|| format!("invalid RAM search comparison {}", comparison))?;
+        Ok(self.search.filter(&self.core.bus, comparison))
+    }
+
+    // { count, results: [{ address, bank (SRAM only, otherwise null), value, previous }] } with at most `limit` results
+    pub fn ram_search_results(&self, limit: usize) -> String {
+        self.search.to_json(&self.core.bus, limit)
+    }
+
+    // start of the last frame in wasm memory, JS can view it without copying:
+    // new Uint8ClampedArray(memory.buffer, framebuffer_ptr(), framebuffer_len())
+    // the view has to be recreated after run_frame or set_color_format since the buffer can move
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.core.bus.get_framebuffer().as_ptr()
+    }
+
+    pub fn framebuffer_len(&self) -> usize {
+        self.core.bus.get_framebuffer().len()
+    }
+
+    // 0 - raw shades (default) | 1 - RGBA8888 | 2 - RGB565 (little endian)
+    pub fn set_color_format(&mut self, format: u8) {
+        self.core.bus.set_color_format(format);
+    }
+
+    // applies to BG, OBP0 and OBP1 alike: 0 - greyscale | 1 - DMG green | 2 - pocket grey | 3 - colors set with set_palette
+    pub fn set_palette_preset(&mut self, preset: u8) {
+        self.core.bus.set_palette_preset(preset);
+    }
+
+    // source: 0 - BG/window | 1 - OBP0 | 2 - OBP1, colors are 0xRRGGBB from lightest to darkest shade
+    pub fn set_palette(&mut self, source: u8, colors: Vec<u32>) -> Result<(), String> {
+        self.core.bus.set_palette(source, &colors).map_err(|err| err.to_string())
     }
 
     pub fn debug_panel(&mut self) -> Vec<usize> {
         self.core.bus.get_debug_panel().to_vec()
     }
 
+    // 128x192 RGBA image of all tiles, palette: 0 - BGP | 1 - OBP0 | 2 - OBP1 | otherwise raw color ids
+    pub fn tile_sheet(&self, palette: u8) -> Vec<u8> {
+        self.core.bus.get_tile_sheet(palette)
+    }
+
+    // 256x256 RGBA image of tile map 0 (0x9800) or 1 (0x9C00) with the viewport outlined
+    pub fn tile_map(&self, map: u8) -> Vec<u8> {
+        self.core.bus.get_tile_map(map)
+    }
+
+    // 8 bytes per sprite: y, x, tile, palette, x flip, y flip, bg priority, OAM index
+    pub fn oam_attributes(&self) -> Vec<u8> {
+        let mut attributes = vec![];
+        for sprite in self.core.bus.get_oam_sprites() {
+            attributes.extend_from_slice(&[sprite.y_pos, sprite.x_pos, sprite.tile_number, sprite.palette, sprite.x_flip as u8, sprite.y_flip as u8, sprite.bg_priority as u8, sprite.index]);
+        }
+        attributes
+    }
+
+    // 64x80 RGBA image of every sprite in OAM order
+    pub fn oam_sheet(&self) -> Vec<u8> {
+        self.core.bus.get_oam_sheet()
+    }
+
+    // layer: 0 - BG | 1 - window | 2 - OBJ, independent of what the game has set in LCDC
+    pub fn set_layer_visible(&mut self, layer: u8, visible: bool) {
+        self.core.bus.set_layer_visible(layer, visible);
+    }
+
+    pub fn set_separate_layers(&mut self, separate: bool) {
+        self.core.bus.set_separate_layers(separate);
+    }
+
+    // layer: 0 - BG | 1 - window | 2 - OBJ | 3 - OAM index of the winning sprite, 0xFF where the layer drew nothing
+    pub fn layer(&self, layer: u8) -> Vec<u8> {
+        self.core.bus.get_layer(layer).to_vec()
+    }
+
+    // 0 - pixel FIFO (default) | 1 - scanline, faster but mid-scanline register writes are missed
+    pub fn set_renderer(&mut self, renderer: u8) {
+        self.core.bus.set_renderer(renderer);
+    }
+
+    pub fn set_timeline_enabled(&mut self, enabled: bool) {
+        self.core.bus.set_timeline_enabled(enabled);
+    }
+
+    // events recorded during the last frame as chrome trace-event JSON
+    pub fn timeline_trace(&self) -> String {
+        self.core.bus.get_timeline_trace()
+    }
+
     pub fn save_file(&mut self) -> Vec<u8> {
         self.core.create_save_file()
     }
 
-    pub fn load_save_file(&mut self, bess_encoding: Vec<u8>) {
-        self.core.load_save_file(bess_encoding);
+    // throws with the reason when the file can't be loaded, the running game is left untouched
+    pub fn load_save_file(&mut self, bess_encoding: Vec<u8>) -> Result<(), String> {
+        self.core.load_save_file(bess_encoding).map_err(|err| err.to_string())
+    }
+
+    // timestamp is whatever the frontend wants to show (Date.now())
+    pub fn save_slot(&mut self, name: String, timestamp: f64) {
+        self.slots.save(&name, &mut self.core, timestamp);
+    }
+
+    // throws if the slot is empty or was saved with another ROM
+    pub fn load_slot(&mut self, name: String) -> Result<(), String> {
+        self.slots.load(&name, &mut self.core).map_err(|err| err.to_string())
+    }
+
+    pub fn delete_slot(&mut self, name: String) -> bool {
+        self.slots.delete(&name)
+    }
+
+    // JSON array of { name, romChecksum, frame, timestamp }
+    pub fn slots(&self) -> String {
+        self.slots.to_json()
+    }
+
+    // 80x72 RGBA image of the screen when the slot was saved, empty if there's no such slot
+    pub fn slot_thumbnail(&self, name: String) -> Vec<u8> {
+        self.slots.thumbnail(&name, &self.core).unwrap_or_default()
+    }
+
+    // BESS file of the slot so it can be downloaded, empty if there's no such slot
+    pub fn slot_save_file(&self, name: String) -> Vec<u8> {
+        self.slots.get(&name).map(|slot| slot.save_file.clone()).unwrap_or_default()
     }
 }
\ No newline at end of file